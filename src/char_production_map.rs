@@ -1,7 +1,8 @@
 
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-use crate::keys::KeyCode;
+use crate::keys::{KeyCode, Event, Pressed, Released};
+use crate::key_transforms::Mapper;
 use KeyCode::*;
 
 lazy_static! {
@@ -119,4 +120,51 @@ fn _char_access_map() -> HashMap<char, SinkKey> {
   res
 }
 
+// Types `text` into `mapper` as a US-layout keyboard would, tapping each
+// key (with Shift where needed), and returns what the mapper sends.
+// Characters with no key in `CHAR_ACCESS_MAP` are skipped with a warning.
+pub fn type_string(mapper: &mut Mapper, text: &str) -> Vec<Event> {
+  let mut events: Vec<Event> = Vec::new();
+  
+  for c in text.chars() {
+    let sink_key = match CHAR_ACCESS_MAP.get(&c) {
+      Some(sink_key) => sink_key,
+      None => {
+        eprintln!("Warning: cannot type {:?}, skipping it", c);
+        continue;
+      }
+    };
+    
+    let mut taps = vec![Pressed(sink_key.k), Released(sink_key.k)];
+    if sink_key.sh {
+      taps.insert(0, Pressed(LEFTSHIFT));
+      taps.push(Released(LEFTSHIFT));
+    }
+    for ev in taps {
+      events.append(&mut mapper.step(ev).events);
+    }
+  }
+  
+  events
+}
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::keys::{Layout, Mapping};
+
+  #[test]
+  fn test_type_string() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![I], to: vec![O], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    assert_eq!(type_string(&mut mapper, "H\u{e9}i"), vec![
+      Pressed(LEFTSHIFT), Pressed(H), Released(H), Released(LEFTSHIFT),
+      Pressed(O), Released(O),
+    ]);
+  }
+}
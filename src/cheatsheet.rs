@@ -0,0 +1,173 @@
+// vim: shiftwidth=2
+
+use crate::keys::{Layout, Mapping, KeyCode, first_output, mappings_with_tag};
+use crate::layout_validation::is_layer;
+
+// Renders the layout as Markdown: one table for mappings that apply
+// without a layer, then one per layer, headed by the layer's trigger.
+// With a `tag`, only mappings that have it are listed, but layers are
+// still found from the whole layout.
+pub fn to_cheatsheet(layout: &Layout, tag: Option<&str>) -> String {
+  let listed: Vec<&Mapping> = match tag {
+    None => layout.mappings.iter().collect(),
+    Some(tag) => mappings_with_tag(layout, tag)
+  };
+  
+  let layers: Vec<&Mapping> = layout.mappings.iter()
+    .filter(|m| first_output(m).is_empty() && is_layer(layout, m))
+    .collect();
+
+  let in_layer = |m: &Mapping, layer: &Mapping| {
+    m.from.len() == layer.from.len() + 1 && m.from[.. layer.from.len()] == layer.from[..]
+  };
+
+  let base: Vec<&Mapping> = listed.iter().cloned()
+    .filter(|m| !layers.contains(m) && !layers.iter().any(|l| in_layer(m, l)))
+    .collect();
+
+  let mut res = String::new();
+  res.push_str(&section("Base", &base, 0));
+
+  for layer in &layers {
+    let mappings: Vec<&Mapping> = listed.iter().cloned()
+      .filter(|m| in_layer(m, layer))
+      .collect();
+    res.push('\n');
+    res.push_str(&section(&join_keys(&layer.from), &mappings, layer.from.len()));
+  }
+
+  res
+}
+
+// `skip` is the number of leading trigger keys that the heading already
+// names. Progressive mappings show their first output.
+fn section(heading: &str, mappings: &[&Mapping], skip: usize) -> String {
+  let mut res = format!("## {}\n\n| Press | Output |\n| --- | --- |\n", heading);
+  for m in mappings {
+    let output = {
+      let to = first_output(m);
+      if to.is_empty() {
+        "(nothing)".to_string()
+      }
+      else {
+        join_keys(to)
+      }
+    };
+    res.push_str(&format!("| {} | {} |\n", join_keys(&m.from[skip ..]), output));
+  }
+  res
+}
+
+fn join_keys(keys: &[KeyCode]) -> String {
+  let names: Vec<String> = keys.iter().map(|k| format!("{}", k)).collect();
+  names.join(" + ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use KeyCode::*;
+
+  #[test]
+  fn test_super_multi_cheatsheet() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![TAB], to: vec![], ..Default::default() },
+        Mapping { from: vec![F], to: vec![U], ..Default::default() },
+        Mapping { from: vec![N], to: vec![B], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, F], to: vec![EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, N], to: vec![LEFTSHIFT, K1], ..Default::default() },
+        Mapping { from: vec![TAB, M], to: vec![PAGEDOWN], ..Default::default() },
+        Mapping { from: vec![TAB, N], to: vec![LEFTCTRL, LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+
+    let expected = "\
+## Base
+
+| Press | Output |
+| --- | --- |
+| F | U |
+| N | B |
+
+## CAPSLOCK
+
+| Press | Output |
+| --- | --- |
+| M | LEFTSHIFT + EQUAL |
+| F | EQUAL |
+| N | LEFTSHIFT + K1 |
+
+## TAB
+
+| Press | Output |
+| --- | --- |
+| M | PAGEDOWN |
+| N | LEFTCTRL + LEFT |
+";
+    assert_eq!(to_cheatsheet(&layout, None), expected);
+  }
+
+  #[test]
+  fn test_progressive_cheatsheet() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping {
+          from: vec![CAPSLOCK, J],
+          to: vec![],
+          progressive_outputs: vec![vec![HOME], vec![LEFTCTRL, HOME]],
+          ..Default::default()
+        },
+      ],
+      ..Default::default()
+    };
+
+    let expected = "\
+## Base
+
+| Press | Output |
+| --- | --- |
+
+## CAPSLOCK
+
+| Press | Output |
+| --- | --- |
+| J | HOME |
+";
+    assert_eq!(to_cheatsheet(&layout, None), expected);
+  }
+
+  #[test]
+  fn test_tagged_cheatsheet() {
+    let nav = vec!["navigation".to_string()];
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![F], to: vec![U], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], tags: nav.clone(), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, L], to: vec![RIGHT], tags: nav, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+
+    let expected = "\
+## Base
+
+| Press | Output |
+| --- | --- |
+
+## CAPSLOCK
+
+| Press | Output |
+| --- | --- |
+| J | LEFT |
+| L | RIGHT |
+";
+    assert_eq!(to_cheatsheet(&layout, Some("navigation")), expected);
+  }
+}
@@ -14,7 +14,7 @@ use uinput_sys::{ui_set_evbit, EV_SYN, EV_KEY, EV_MSC, ui_dev_create, ui_set_key
 use crate::struct_ser::StructSerializer;
 use std::os::unix::io::RawFd;
 use crate::keys::Event;
-use num_traits::FromPrimitive;
+use crate::key_codes::{KeyCodeTable, LinuxEvdev};
 use std::path::Path;
 use ioctls::{eviocgkey, eviocgrab};
 
@@ -42,7 +42,7 @@ impl DevInputReader {
       let value = i32::from_ne_bytes([buf[20], buf[21], buf[22], buf[23]]);
       
       if type_ == 1 && (value == 0 || value == 1) {
-        match FromPrimitive::from_u16(code) {
+        match LinuxEvdev.from_code(code) {
           Some(k) => match value {
             1 => return Ok(Event::Pressed(k)),
             0 => return Ok(Event::Released(k)),
@@ -210,7 +210,7 @@ impl DevInputWriter {
         Event::Released(_) => 0
       };
       
-      let code = (*k) as u16;
+      let code = LinuxEvdev.to_code(*k);
       
       send_type_code_value(1, code, value);
     }
@@ -1,9 +1,7 @@
-
 use crate::key_codes::KeyCode;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Event {
   Pressed(KeyCode),
   Released(KeyCode)
 }
-
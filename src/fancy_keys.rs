@@ -5,6 +5,7 @@ use std::fmt::Display;
  
 pub use crate::key_codes::KeyCode; 
 pub use crate::events::Event;
+pub use crate::keys::LayerAction;
 pub use Event::Pressed;
 pub use Event::Released;
 
@@ -32,7 +33,8 @@ pub struct SingleMapping {
   pub from: SingleFromKeys,
   pub to: SingleToKeys,
   pub repeat: SingleRepeat,
-  pub absorbing: Vec<Modifier>
+  pub absorbing: Vec<Modifier>,
+  pub options: MappingOptions
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -46,7 +48,25 @@ pub struct RowMapping {
   pub from: RowFromKeys,
   pub to: RowToKeys,
   pub repeat: RowRepeat,
-  pub absorbing: Vec<Modifier>
+  pub absorbing: Vec<Modifier>,
+  pub options: MappingOptions
+}
+
+// Settings that are copied as they are onto each mapping that a single or
+// row mapping turns into
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct MappingOptions {
+  pub ignore_extra_modifiers: bool,
+  pub layer: Option<LayerAction>,
+  pub clear_first: bool,
+  pub ordered: bool,
+  pub silent_release: bool,
+  pub sticky_layer: bool,
+  pub clear_on_next_key: bool,
+  pub progressive_outputs: Vec<Vec<KeyCode>>,
+  pub release_override: Option<Vec<KeyCode>>,
+  pub load_layout: Option<usize>,
+  pub tags: Vec<String>
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -31,7 +31,8 @@ pub fn convert(f: &f::Layout) -> Result<s::Layout, String> {
   }
   
   Ok(s::Layout {
-    mappings: res
+    mappings: res,
+    ..Default::default()
   })
 }
 
@@ -61,7 +62,7 @@ fn adjust_repeats<'a>(res: &mut Vec<s::Mapping>, from_table: &HashMap<FromSet, V
           }
         }
         else {
-          res.push(s::Mapping { from: from.clone(), to: from, repeat, absorbing: vec![] });
+          res.push(s::Mapping { from: from.clone(), to: from, repeat, absorbing: vec![], ..Default::default() });
         }
       }
     },
@@ -105,7 +106,8 @@ fn convert_alias(alias: &f::AliasMapping) -> Vec<s::Mapping> {
       from: alias.from.keys.clone(),
       to: alias.to.initial.clone(),
       repeat: s::Repeat::Normal,
-      absorbing: vec![]
+      absorbing: vec![],
+      ..Default::default()
     }]
   }
   else {
@@ -134,12 +136,13 @@ fn convert_single<'a>(alias_mappings: &'a HashMap<String, Vec<&'a f::AliasMappin
 
     let absorbing = modifier_combination.reify_modifiers(&single.absorbing)?;
 
-    res.push(s::Mapping {
+    res.push(with_options(s::Mapping {
       from,
       to,
       repeat,
-      absorbing
-    });
+      absorbing,
+      ..Default::default()
+    }, &single.options));
   }
   Ok(res)
 }
@@ -216,18 +219,36 @@ fn convert_row<'t>(alias_mappings: &'t HashMap<String, Vec<&'t f::AliasMapping>>
 
         let absorbing = modifier_combination.reify_modifiers(&row_mapping.absorbing)?;
 
-        res.push(s::Mapping {
+        res.push(with_options(s::Mapping {
           from,
           to,
           repeat,
-          absorbing
-        });
+          absorbing,
+          ..Default::default()
+        }, &row_mapping.options));
       }
     }
   }
   Ok(res)
 }
 
+fn with_options(mapping: s::Mapping, options: &f::MappingOptions) -> s::Mapping {
+  s::Mapping {
+    ignore_extra_modifiers: options.ignore_extra_modifiers,
+    layer: options.layer.clone(),
+    clear_first: options.clear_first,
+    ordered: options.ordered,
+    silent_release: options.silent_release,
+    sticky_layer: options.sticky_layer,
+    clear_on_next_key: options.clear_on_next_key,
+    progressive_outputs: options.progressive_outputs.clone(),
+    release_override: options.release_override.clone(),
+    load_layout: options.load_layout,
+    tags: options.tags.clone(),
+    ..mapping
+  }
+}
+
 fn find_right_shift(from: &Vec<KeyCode>) -> bool {
   for k in from {
     if *k == KeyCode::RIGHTSHIFT {
@@ -536,16 +557,17 @@ use super::{multiply, convert_row_to, convert};
       from: SFK { modifiers: vec![Alias("@shift".o())], key: E },
       to: STK { initial: vec![Alias("@shift".o())], terminal: Physical(DOT) },
       repeat: f::SingleRepeat::Special { keys: STK { initial: vec![Key(LEFTCTRL)], terminal: Physical(K3) }, delay_ms: 50, interval_ms: 30 },
-      absorbing: vec![Alias("@shift".o())]
+      absorbing: vec![Alias("@shift".o())],
+      options: f::MappingOptions::default()
     };
     
     let res = convert_single(&alias_mappings, &single).unwrap();
     assert_eq!(res.len(), 2);
     assert_eq!(res[0], 
-      s::Mapping { from: vec![LEFTSHIFT, E], to: vec![LEFTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LEFTSHIFT]  }
+      s::Mapping { from: vec![LEFTSHIFT, E], to: vec![LEFTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LEFTSHIFT], ..Default::default() }
     );
     assert_eq!(res[1],
-      s::Mapping { from: vec![RIGHTSHIFT, E], to: vec![RIGHTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RIGHTSHIFT]  }
+      s::Mapping { from: vec![RIGHTSHIFT, E], to: vec![RIGHTSHIFT, DOT], repeat: s::Repeat::Special { keys: vec![LEFTCTRL, K3], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RIGHTSHIFT], ..Default::default() }
     );
   }
   
@@ -563,7 +585,8 @@ use super::{multiply, convert_row_to, convert};
       from: RFK { modifiers: vec![Alias("@shift".o())], row: f::Row::USQuertyA },
       to: RTK { initial: vec![], terminal: "AOEU".o() },
       repeat: f::RowRepeat::Special { keys: RTK { initial: vec![], terminal: "aoeu".o() }, delay_ms: 50, interval_ms: 30 },
-      absorbing: vec![Alias("@shift".o())]
+      absorbing: vec![Alias("@shift".o())],
+      options: f::MappingOptions::default()
     };
     
     use s::Repeat::Special as SRS;
@@ -575,15 +598,15 @@ use super::{multiply, convert_row_to, convert};
     let res = convert_row(&alias_mappings, &row).unwrap();
     assert_eq!(res.len(), 8);
     
-    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
-    assert_eq!(res[1], SM { from: vec![LS, S], to: vec![LS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
-    assert_eq!(res[2], SM { from: vec![LS, D], to: vec![LS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
-    assert_eq!(res[3], SM { from: vec![LS, F], to: vec![LS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS]  });
+    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
+    assert_eq!(res[1], SM { from: vec![LS, S], to: vec![LS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
+    assert_eq!(res[2], SM { from: vec![LS, D], to: vec![LS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
+    assert_eq!(res[3], SM { from: vec![LS, F], to: vec![LS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![LS], ..Default::default() });
     
-    assert_eq!(res[4], SM { from: vec![RS, A], to: vec![RS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
-    assert_eq!(res[5], SM { from: vec![RS, S], to: vec![RS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
-    assert_eq!(res[6], SM { from: vec![RS, D], to: vec![RS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
-    assert_eq!(res[7], SM { from: vec![RS, F], to: vec![RS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS]  });
+    assert_eq!(res[4], SM { from: vec![RS, A], to: vec![RS, A], repeat: SRS { keys: vec![A], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
+    assert_eq!(res[5], SM { from: vec![RS, S], to: vec![RS, O], repeat: SRS { keys: vec![O], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
+    assert_eq!(res[6], SM { from: vec![RS, D], to: vec![RS, E], repeat: SRS { keys: vec![E], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
+    assert_eq!(res[7], SM { from: vec![RS, F], to: vec![RS, U], repeat: SRS { keys: vec![U], delay_ms: 50, interval_ms: 30 }, absorbing: vec![RS], ..Default::default() });
   }
   
   #[test]
@@ -598,7 +621,8 @@ use super::{multiply, convert_row_to, convert};
       from: RFK { modifiers: vec![Alias("@shift".o())], row: f::Row::USQuertyA },
       to: RTK { initial: vec![], terminal: "A".o() },
       repeat: f::RowRepeat::Normal,
-      absorbing: vec![]
+      absorbing: vec![],
+      options: f::MappingOptions::default()
     };
     
     use s::Mapping as SM;
@@ -607,7 +631,7 @@ use super::{multiply, convert_row_to, convert};
     
     let res = convert_row(&alias_mappings, &row).unwrap();
     assert_eq!(res.len(), 1);
-    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: s::Repeat::Normal, absorbing: vec![]  });
+    assert_eq!(res[0], SM { from: vec![LS, A], to: vec![LS, A], repeat: s::Repeat::Normal, absorbing: vec![], ..Default::default() });
   }
   
   #[test]
@@ -635,7 +659,7 @@ use super::{multiply, convert_row_to, convert};
     use s::Mapping as SM;
     use KeyCode::LEFTSHIFT as LS;
     assert_eq!(simple_layout.mappings[0], SM { from: vec![LS, A], to: vec![LS, S], repeat: s::Repeat::Special {
-      keys: vec![F24], delay_ms: 180, interval_ms: 30 }, absorbing: vec![] });
+      keys: vec![F24], delay_ms: 180, interval_ms: 30 }, absorbing: vec![], ..Default::default() });
   }
 
   #[test]
@@ -651,8 +675,8 @@ use super::{multiply, convert_row_to, convert};
     let simple_layout = convert(&fancy_layout).unwrap();
     assert_eq!(simple_layout.mappings.len(), 2);
     use s::Mapping as SM;
-    assert_eq!(simple_layout.mappings[0], SM { from: vec![CAPSLOCK], to: vec![], repeat: s::Repeat::Normal, absorbing: vec![] });
-    assert_eq!(simple_layout.mappings[1], SM { from: vec![CAPSLOCK, Q], to: vec![ESC], repeat: s::Repeat::Normal, absorbing: vec![] });
+    assert_eq!(simple_layout.mappings[0], SM { from: vec![CAPSLOCK], to: vec![], repeat: s::Repeat::Normal, absorbing: vec![], ..Default::default() });
+    assert_eq!(simple_layout.mappings[1], SM { from: vec![CAPSLOCK, Q], to: vec![ESC], repeat: s::Repeat::Normal, absorbing: vec![], ..Default::default() });
   }
 }
 
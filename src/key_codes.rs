@@ -4,6 +4,7 @@
 use serde::{Deserialize, Serialize};
 use enum_utils::FromStr;
 use num_derive::FromPrimitive;
+use num_traits::FromPrimitive as _;
 use std::fmt::Display;
 
 #[derive(Debug, Display, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, FromStr, FromPrimitive, Serialize, Deserialize)]
@@ -506,7 +507,58 @@ pub enum KeyCode {
   KBD_LCD_MENU5 = 700,
 }
 
-#[allow(dead_code)]
+// Translates between `KeyCode` and the numeric codes of an input backend.
+// The rest of the program only deals in `KeyCode`s; this is used at the
+// boundary where events are read and written.
+pub trait KeyCodeTable {
+  fn to_code(&self, k: KeyCode) -> u16;
+  #[allow(clippy::wrong_self_convention)]
+  fn from_code(&self, c: u16) -> Option<KeyCode>;
+}
+
+// Linux evdev codes, which `KeyCode`'s discriminants are taken from
+pub struct LinuxEvdev;
+
+impl KeyCodeTable for LinuxEvdev {
+  fn to_code(&self, k: KeyCode) -> u16 {
+    k as u16
+  }
+  
+  fn from_code(&self, c: u16) -> Option<KeyCode> {
+    KeyCode::from_u16(c)
+  }
+}
+
+// X11 keysym names for the keys of a US layout, as they are named when
+// typed without modifiers. Not exhaustive.
+const KEYSYM_NAMES: &[(KeyCode, &str)] = {
+  use KeyCode::*;
+  &[
+    (A, "a"), (B, "b"), (C, "c"), (D, "d"), (E, "e"), (F, "f"), (G, "g"), (H, "h"), (I, "i"),
+    (J, "j"), (K, "k"), (L, "l"), (M, "m"), (N, "n"), (O, "o"), (P, "p"), (Q, "q"), (R, "r"),
+    (S, "s"), (T, "t"), (U, "u"), (V, "v"), (W, "w"), (X, "x"), (Y, "y"), (Z, "z"),
+    (K0, "0"), (K1, "1"), (K2, "2"), (K3, "3"), (K4, "4"), (K5, "5"), (K6, "6"), (K7, "7"),
+    (K8, "8"), (K9, "9"),
+    (F1, "F1"), (F2, "F2"), (F3, "F3"), (F4, "F4"), (F5, "F5"), (F6, "F6"), (F7, "F7"), (F8, "F8"),
+    (F9, "F9"), (F10, "F10"), (F11, "F11"), (F12, "F12"),
+    (MINUS, "minus"), (EQUAL, "equal"), (LEFTBRACE, "bracketleft"), (RIGHTBRACE, "bracketright"),
+    (SEMICOLON, "semicolon"), (APOSTROPHE, "apostrophe"), (GRAVE, "grave"),
+    (BACKSLASH, "backslash"), (COMMA, "comma"), (DOT, "period"), (SLASH, "slash"), (SPACE, "space"),
+    (ESC, "Escape"), (TAB, "Tab"), (ENTER, "Return"), (BACKSPACE, "BackSpace"), (DELETE, "Delete"),
+    (INSERT, "Insert"), (HOME, "Home"), (END, "End"), (PAGEUP, "Prior"), (PAGEDOWN, "Next"),
+    (LEFT, "Left"), (RIGHT, "Right"), (UP, "Up"), (DOWN, "Down"), (SYSRQ, "Print"),
+    (PAUSE, "Pause"), (COMPOSE, "Menu"),
+    (LEFTSHIFT, "Shift_L"), (RIGHTSHIFT, "Shift_R"), (LEFTCTRL, "Control_L"),
+    (RIGHTCTRL, "Control_R"), (LEFTALT, "Alt_L"), (RIGHTALT, "Alt_R"), (LEFTMETA, "Super_L"),
+    (RIGHTMETA, "Super_R"), (CAPSLOCK, "Caps_Lock"), (NUMLOCK, "Num_Lock"),
+    (SCROLLLOCK, "Scroll_Lock"),
+  ]
+};
+
+pub fn from_keysym_name(name: &str) -> Option<KeyCode> {
+  KEYSYM_NAMES.iter().find(|(_, name2)| *name2 == name).map(|(k, _)| *k)
+}
+
 pub fn get_all_keyboard_key_codes() -> Vec<KeyCode> {
   use KeyCode::*;
   
@@ -554,3 +606,62 @@ pub fn get_all_keyboard_key_codes() -> Vec<KeyCode> {
   ]  
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use KeyCode::*;
+  
+  #[test]
+  fn test_linux_evdev() {
+    assert_eq!(LinuxEvdev.to_code(A), 30);
+    assert_eq!(LinuxEvdev.from_code(30), Some(A));
+    assert_eq!(LinuxEvdev.from_code(0), None);
+  }
+  
+  #[test]
+  fn test_custom_table() {
+    // Swaps the codes of A and B, otherwise like evdev
+    struct Swapped;
+    
+    impl KeyCodeTable for Swapped {
+      fn to_code(&self, k: KeyCode) -> u16 {
+        match k {
+          A => LinuxEvdev.to_code(B),
+          B => LinuxEvdev.to_code(A),
+          k => LinuxEvdev.to_code(k)
+        }
+      }
+      
+      fn from_code(&self, c: u16) -> Option<KeyCode> {
+        match LinuxEvdev.from_code(c) {
+          Some(A) => Some(B),
+          Some(B) => Some(A),
+          k => k
+        }
+      }
+    }
+    
+    assert_eq!(Swapped.to_code(A), 48);
+    assert_eq!(Swapped.from_code(48), Some(A));
+    assert_eq!(Swapped.to_code(C), 46);
+    assert_eq!(Swapped.from_code(46), Some(C));
+  }
+  
+  #[test]
+  fn test_keysym_names() {
+    assert_eq!(from_keysym_name("a"), Some(A));
+    assert_eq!(from_keysym_name("period"), Some(DOT));
+    assert_eq!(from_keysym_name("Control_L"), Some(LEFTCTRL));
+    assert_eq!(from_keysym_name("Prior"), Some(PAGEUP));
+    
+    for (k, name) in KEYSYM_NAMES {
+      assert_eq!(from_keysym_name(name), Some(*k));
+    }
+  }
+  
+  #[test]
+  fn test_unknown_keysym_names() {
+    assert_eq!(from_keysym_name("XF86AudioMute"), None);
+    assert_eq!(from_keysym_name("A"), None);
+  }
+}
@@ -1,15 +1,17 @@
 
 // vim: shiftwidth=2
 
-use crate::keys::{Layout, Mapping, KeyCode, Pressed, Released, Event, Repeat};
+use crate::keys::{Layout, Mapping, KeyCode, Pressed, Released, Event, Repeat, LayerAction, first_output};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use serde::Serialize;
 
 fn final_key(trigger: &Vec<KeyCode>) -> KeyCode {
   return trigger[trigger.len() - 1];
 }
 
-fn is_supported(trigger: &Vec<KeyCode>, pressed_keys: &Vec<KeyCode>, absorbed_keys: &Vec<KeyCode>, new_key: &KeyCode) -> bool {
+fn is_supported(trigger: &[KeyCode], pressed_keys: &[KeyCode], absorbed_keys: &[KeyCode], new_key: &KeyCode) -> bool {
   for k in trigger {
     if !((pressed_keys.contains(&k) && !absorbed_keys.contains(&k)) || k == new_key) {
       return false;
@@ -18,6 +20,15 @@ fn is_supported(trigger: &Vec<KeyCode>, pressed_keys: &Vec<KeyCode>, absorbed_ke
   return true;
 }
 
+// Whether the keys of `trigger` before its final key were pressed in the
+// order they are listed. `pressed_keys` is in the order keys were pressed.
+fn follows_order(trigger: &[KeyCode], pressed_keys: &[KeyCode]) -> bool {
+  let positions: Vec<Option<usize>> = trigger[.. trigger.len() - 1].iter()
+    .map(|k| pressed_keys.iter().position(|k2| k2 == k))
+    .collect();
+  positions.windows(2).all(|w| w[0] < w[1])
+}
+
 fn fails_when_released(trigger: &Vec<KeyCode>, key: &KeyCode) -> bool {
   for k in trigger {
     if k == key {
@@ -35,7 +46,47 @@ struct State {
   mapped_output_keys: Vec<KeyCode>,
   mapped_absorbed_keys: Vec<KeyCode>,
   absorbing_trigger: Option<KeyCode>,
-  repeating_trigger: Option<KeyCode>
+  repeating_trigger: Option<KeyCode>,
+  layer_stack: Vec<KeyCode>,
+  one_shot_layer: Option<KeyCode>,
+  // How many times each progressive mapping, by trigger, has fired while
+  // the rest of its trigger has been held
+  progress: HashMap<Vec<KeyCode>, usize>,
+  // Pass-through keys that mappings never take over, even when they are
+  // part of the mapping's trigger or output
+  pinned_passthrough_keys: Vec<KeyCode>,
+  // Set by a mapping with `load_layout`, and carried out once the step
+  // that fired it is done
+  pending_layout: Option<usize>,
+  // Modifiers that latch when tapped on their own, until the next
+  // non-modifier key press
+  latch_modifiers: Vec<KeyCode>,
+  // Latched modifiers, in the order they were tapped. They are held on
+  // the output but no longer tracked as pass-through keys.
+  latched: Vec<KeyCode>,
+  // A latch modifier that has been pressed with no other key since
+  latch_candidate: Option<KeyCode>,
+  // Modifiers released because a mapping with `ignore_extra_modifiers`
+  // fired, with that mapping's trigger. They are pressed again when it
+  // ends, if still wanted.
+  suppressed_modifiers: Vec<(Vec<KeyCode>, KeyCode)>
+}
+
+// A snapshot of the mapper's internal state, for dumping to JSON when
+// tracking down stuck keys.
+#[derive(Debug, Serialize)]
+pub struct DebugState {
+  pub input_pressed_keys: Vec<KeyCode>,
+  pub active_mappings: Vec<DebugMapping>,
+  pub pass_through_keys: Vec<KeyCode>,
+  pub mapped_output_keys: Vec<KeyCode>,
+  pub layer_stack: Vec<KeyCode>
+}
+
+#[derive(Debug, Serialize)]
+pub struct DebugMapping {
+  pub from: Vec<KeyCode>,
+  pub to: Vec<KeyCode>
 }
 
 impl State {
@@ -48,15 +99,34 @@ impl State {
       mapped_absorbed_keys: Vec::new(),
       absorbing_trigger: None,
       repeating_trigger: None,
+      layer_stack: Vec::new(),
+      one_shot_layer: None,
+      progress: HashMap::new(),
+      pinned_passthrough_keys: Vec::new(),
+      pending_layout: None,
+      latch_modifiers: Vec::new(),
+      latched: Vec::new(),
+      latch_candidate: None,
+      suppressed_modifiers: Vec::new()
     };
   }
 }
 
-struct HashedLayout {
-  mappings: HashMap<KeyCode, Vec<Mapping>>
+pub struct HashedLayout {
+  mappings: HashMap<KeyCode, Vec<Mapping>>,
+  debug_dump_sequence: Option<Vec<KeyCode>>
+}
+
+impl HashedLayout {
+  fn contains(&self, m: &Mapping) -> bool {
+    match self.mappings.get(&final_key(&m.from)) {
+      None => false,
+      Some(candidates) => candidates.contains(m)
+    }
+  }
 }
 
-fn make_hashed_layout(layout: &Layout) -> HashedLayout {
+pub fn make_hashed_layout(layout: &Layout) -> HashedLayout {
   let mut mappings: HashMap<KeyCode, Vec<Mapping>> = HashMap::new();
 
   for mapping in &layout.mappings {
@@ -90,12 +160,66 @@ fn make_hashed_layout(layout: &Layout) -> HashedLayout {
     }
   }
   
-  HashedLayout { mappings }
+  HashedLayout { mappings, debug_dump_sequence: layout.debug_dump_sequence.clone() }
+}
+
+// Whether `m` can fire given the held keys, ignoring any preference
+// between mappings
+pub fn can_fire(m: &Mapping, pressed: &[KeyCode], new_key: KeyCode) -> bool {
+  is_supported(&m.from, pressed, &[], &new_key)
+    && (!m.ordered || follows_order(&m.from, pressed))
+}
+
+// Chooses which of the mappings for a newly pressed key fires.
+// `candidates` are all mappings whose trigger ends in `new_key`, in layout
+// order; `pressed` holds the other keys that count as held, in the order
+// they were pressed. When several mappings could fire, the one declared
+// last in the layout wins, regardless of trigger length or how many
+// modifiers it uses. Layouts rely on this to let layer mappings override
+// shifted ones.
+pub fn select_mapping(candidates: &[Mapping], pressed: &[KeyCode], new_key: KeyCode) -> Option<usize> {
+  candidates.iter().rposition(|m| can_fire(m, pressed, new_key))
+}
+
+// What pressing each key that ends some trigger would send next while
+// `held` is down: the output of the mapping that would fire, or the key
+// itself if none would. Modifiers and keys already held are left out.
+pub fn reachable_outputs(layout: &Layout, held: &[KeyCode]) -> Vec<(KeyCode, Vec<KeyCode>)> {
+  let mut keys: Vec<KeyCode> = Vec::new();
+  for m in &layout.mappings {
+    let k = final_key(&m.from);
+    if is_action_key(&k) && !held.contains(&k) && !keys.contains(&k) {
+      keys.push(k);
+    }
+  }
+  
+  keys.into_iter().map(|k| {
+    let candidates: Vec<Mapping> = layout.mappings.iter()
+      .filter(|m| final_key(&m.from) == k)
+      .cloned()
+      .collect();
+    let to = match select_mapping(&candidates, held, k) {
+      Some(i) => first_output(&candidates[i]).clone(),
+      None => vec![k]
+    };
+    (k, to)
+  }).collect()
 }
 
 pub struct Mapper {
-  layout: HashedLayout,
-  state: State
+  layout: Arc<HashedLayout>,
+  state: State,
+  // The layouts that mappings can switch to with `load_layout`
+  layouts: Vec<Arc<HashedLayout>>,
+  // Events for these keys are returned from `step` as they are, without
+  // going through the mapper at all
+  fast_bypass: HashSet<KeyCode>,
+  // Called with the state when the layout's `debug_dump_sequence` is
+  // pressed. It is logged if there is none.
+  debug_dump_handler: Option<Box<dyn FnMut(DebugState)>>,
+  // Whether the final key of the dump sequence is held, so its release is
+  // swallowed too
+  debug_dump_held: bool
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -131,19 +255,89 @@ impl StepResult {
 
 impl Mapper {
   pub fn for_layout(layout: &Layout) -> Mapper {
+    let main = Arc::new(make_hashed_layout(layout));
+    let mut mapper = Mapper::for_hashed_layout(main.clone());
+    mapper.state.pinned_passthrough_keys = layout.pinned_passthrough_keys.clone();
+    mapper.fast_bypass = layout.fast_bypass.iter().cloned().collect();
+    mapper.state.latch_modifiers = layout.latch_modifiers.clone();
+    
+    if !layout.layouts.is_empty() {
+      let mut layouts = vec![main];
+      for other in &layout.layouts {
+        // The dump sequence is a setting of the whole file, so it carries
+        // over to whichever layout is loaded
+        let other = Layout { debug_dump_sequence: layout.debug_dump_sequence.clone(), ..other.clone() };
+        layouts.push(Arc::new(make_hashed_layout(&other)));
+      }
+      mapper.set_preloaded_layouts(layouts);
+    }
+    
+    mapper
+  }
+  
+  pub fn for_hashed_layout(layout: Arc<HashedLayout>) -> Mapper {
     Mapper {
-      layout: make_hashed_layout(layout),
-      state: State::init()
+      layout,
+      state: State::init(),
+      layouts: Vec::new(),
+      fast_bypass: HashSet::new(),
+      debug_dump_handler: None,
+      debug_dump_held: false
+    }
+  }
+  
+  // Switches to an already-compiled layout. Active mappings that do not
+  // exist in the new layout are removed, releasing their outputs; held keys
+  // that fed them stay pressed but produce nothing until released.
+  fn swap_layout(self: &mut Mapper, new: Arc<HashedLayout>) -> Vec<Event> {
+    let mut events: Vec<Event> = Vec::new();
+    
+    let state = &mut self.state;
+    let mut i: isize = state.active_mappings.len() as isize - 1;
+    while i >= 0 {
+      let m = &state.active_mappings[i as usize];
+      if !new.contains(m) {
+        let removed_key = final_key(&m.from);
+        events.append(&mut remove_mapping(state, i as usize, removed_key));
+      }
+      i -= 1;
     }
+    
+    self.layout = new;
+    
+    events
+  }
+  
+  // Sets the layouts that mappings with `load_layout` refer to by index.
+  // A `load_layout` index with no layout here does nothing.
+  fn set_preloaded_layouts(self: &mut Mapper, layouts: Vec<Arc<HashedLayout>>) {
+    self.layouts = layouts;
   }
   
   pub fn step(self: &mut Mapper, input: Event) -> StepResult {
+    if self.bypasses(input) {
+      return StepResult { events: vec![input], repeat: ResultingRepeat::NoChange };
+    }
+    if self.step_debug_dump(input) {
+      return StepResult {
+        events: vec![],
+        repeat: ResultingRepeat::NoChange
+      };
+    }
+    
     let state = &mut self.state;
 
     match input {
       Pressed(k) => {
         if !state.input_pressed_keys.contains(&k) {
-          newly_press(self, k)
+          let mut res = newly_press(self, k);
+          if let Some(i) = self.state.pending_layout.take() {
+            if let Some(layout) = self.layouts.get(i).cloned() {
+              res.events.append(&mut self.swap_layout(layout));
+            }
+          }
+          update_latches(&mut self.state, input, &mut res.events);
+          res
         }
         else {
           StepResult {
@@ -154,7 +348,9 @@ impl Mapper {
       },
       Released(k) => {
         if state.input_pressed_keys.contains(&k) {
-          newly_release(self, k)
+          let mut res = newly_release(self, k);
+          update_latches(&mut self.state, input, &mut res.events);
+          res
         }
         else {
           StepResult {
@@ -166,6 +362,66 @@ impl Mapper {
     }
   }
   
+  pub fn debug_state(&self) -> DebugState {
+    let state = &self.state;
+    DebugState {
+      input_pressed_keys: state.input_pressed_keys.clone(),
+      active_mappings: state.active_mappings.iter().map(|m| DebugMapping {
+        from: m.from.clone(),
+        to: m.to.clone()
+      }).collect(),
+      pass_through_keys: state.pass_through_keys.clone(),
+      mapped_output_keys: state.mapped_output_keys.clone(),
+      layer_stack: state.layer_stack.clone()
+    }
+  }
+  
+  // Hands the state to `handler` instead of logging it when the layout's
+  // `debug_dump_sequence` is pressed, so tests can look at it
+  #[cfg(test)]
+  pub fn set_debug_dump_handler(self: &mut Mapper, handler: Box<dyn FnMut(DebugState)>) {
+    self.debug_dump_handler = Some(handler);
+  }
+  
+  // Whether `input` was taken by the layout's debug dump sequence.
+  // Pressing its final key while holding the rest dumps the state; the
+  // rest of the sequence is mapped as usual.
+  fn step_debug_dump(self: &mut Mapper, input: Event) -> bool {
+    let layout = self.layout.clone();
+    let sequence = match &layout.debug_dump_sequence {
+      Some(sequence) if !sequence.is_empty() => sequence,
+      _ => return false
+    };
+    let last = final_key(sequence);
+    
+    match input {
+      Pressed(k) if k == last => {
+        let rest = &sequence[.. sequence.len() - 1];
+        if !rest.iter().all(|k2| self.state.input_pressed_keys.contains(k2)) {
+          return self.debug_dump_held;
+        }
+        self.debug_dump_held = true;
+        let state = self.debug_state();
+        match &mut self.debug_dump_handler {
+          Some(handler) => handler(state),
+          None => eprintln!("Mapper state: {}", serde_json::to_string(&state).unwrap())
+        }
+        true
+      },
+      Released(k) if k == last && self.debug_dump_held => {
+        self.debug_dump_held = false;
+        true
+      },
+      _ => false
+    }
+  }
+  
+  fn bypasses(self: &Mapper, input: Event) -> bool {
+    match input {
+      Pressed(k) | Released(k) => self.fast_bypass.contains(&k)
+    }
+  }
+  
   pub fn release_all(self: &mut Mapper) -> Vec<Event> {
     let to_release = self.state.input_pressed_keys.clone();
     
@@ -176,11 +432,17 @@ impl Mapper {
       events.append(&mut chunk.events);
     }
     
+    for k in self.state.latched.drain(..).rev() {
+      events.push(Released(k));
+    }
+    self.state.latch_candidate = None;
+    
+    
     events
   }
 }
 
-fn is_action_key(k: &KeyCode) -> bool {
+pub fn is_action_key(k: &KeyCode) -> bool {
   use KeyCode::{LEFTSHIFT, RIGHTSHIFT, LEFTMETA, RIGHTMETA, LEFTCTRL, RIGHTCTRL, LEFTALT, RIGHTALT};
   
   match k {
@@ -196,6 +458,12 @@ fn is_action_key(k: &KeyCode) -> bool {
   }
 }
 
+fn is_locking_key(k: &KeyCode) -> bool {
+  use KeyCode::{CAPSLOCK, NUMLOCK, SCROLLLOCK};
+  
+  matches!(k, CAPSLOCK | NUMLOCK | SCROLLLOCK)
+}
+
 fn is_action_mapping(m: &Mapping) -> bool {
   if m.to.len() == 0 {
     false
@@ -210,15 +478,32 @@ fn is_any_modifier(keys: &Vec<KeyCode>) -> bool {
   keys.iter().any(|k| !is_action_key(k))
 }
 
-fn release_action_mappings(state: &mut State) -> Vec<Event> {
+// Whether `m` is in a layer whose key is held and has `sticky_layer` set
+fn in_sticky_layer(state: &State, m: &Mapping) -> bool {
+  state.active_mappings.iter().any(|layer| {
+    layer.sticky_layer
+      && layer.from.len() < m.from.len()
+      && layer.from.iter().all(|k| m.from[.. m.from.len() - 1].contains(k))
+  })
+}
+
+// Modifiers in `keep` are left held, so that consecutive mappings needing
+// the same modifier don't release and re-press it between them.
+fn release_action_mappings(state: &mut State, keep: &[KeyCode]) -> Vec<Event> {
   let mut events = Vec::new();
   
   let mut keys_to_release: Vec<KeyCode> = Vec::new();
   for exsting_mapping in &state.active_mappings {
+    if in_sticky_layer(state, exsting_mapping) {
+      continue;
+    }
     if is_action_mapping(exsting_mapping) {
       if exsting_mapping.to.len() > 1 && is_any_modifier(&exsting_mapping.to) {
         for mod_key in exsting_mapping.to.iter().rev() {
-          if state.mapped_output_keys.contains(mod_key) {
+          if !is_action_key(mod_key) && keep.contains(mod_key) {
+            continue;
+          }
+          if state.mapped_output_keys.contains(mod_key) && !keys_to_release.contains(mod_key) {
             keys_to_release.push(*mod_key);
           }
         }
@@ -240,19 +525,49 @@ fn release_action_mappings(state: &mut State) -> Vec<Event> {
 }
 
 fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepResult {
+  if m.clear_first {
+    return add_clear_first_mapping(state, m);
+  }
+  
+  let progressive;
+  let m = {
+    if m.progressive_outputs.is_empty() {
+      m
+    }
+    else {
+      let to = next_output(state, m).clone();
+      *state.progress.entry(m.from.clone()).or_insert(0) += 1;
+      progressive = Mapping { to, ..m.clone() };
+      &progressive
+    }
+  };
+  
   let mut events: Vec<Event> = Vec::new();
   
   let pass_through_keys = &mut state.pass_through_keys;
   let mapped_output_keys = &mut state.mapped_output_keys;
+  let pinned = &state.pinned_passthrough_keys;
+  
+  // Pass-through keys that become part of this mapping's output
+  let mut adopted: Vec<KeyCode> = Vec::new();
+  let mut suppressed: Vec<KeyCode> = Vec::new();
   
   pass_through_keys.retain(|&old_key| {
-    if m.from.contains(&old_key) || m.to.contains(&old_key) {
+    if pinned.contains(&old_key) {
+      return true;
+    }
+    let is_extra_modifier = m.ignore_extra_modifiers && !is_action_key(&old_key);
+    if m.from.contains(&old_key) || m.to.contains(&old_key) || is_extra_modifier {
       if !m.to.contains(&old_key) {
         events.push(Released(old_key));
+        if !m.from.contains(&old_key) {
+          suppressed.push(old_key);
+        }
         false
       }
       else {
         mapped_output_keys.push(old_key);
+        adopted.push(old_key);
         false
       }
     }
@@ -261,8 +576,27 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
     }
   });
   
+  // Modifiers held by other mappings, such as a layer key mapped to
+  // LEFTCTRL, are extra too
+  if m.ignore_extra_modifiers {
+    mapped_output_keys.retain(|&old_key| {
+      if !is_action_key(&old_key) && !m.to.contains(&old_key) {
+        events.push(Released(old_key));
+        suppressed.push(old_key);
+        false
+      }
+      else {
+        true
+      }
+    });
+  }
+  
+  for k in suppressed {
+    state.suppressed_modifiers.push((m.from.clone(), k));
+  }
+  
   if is_action_mapping(m) {
-    events.append(&mut release_action_mappings(state));
+    events.append(&mut release_action_mappings(state, &m.to));
     let should_absorb = {
       match &state.absorbing_trigger {
         Some(absorbing_trigger) => *absorbing_trigger != *new_key,
@@ -276,14 +610,23 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
   
   for new_key in &m.to {
     if is_action_key(new_key) {
+      // Re-triggering a lock key would toggle the lock twice, so an
+      // already-held lock key is left as it is.
       if state.mapped_output_keys.contains(new_key) {
-        events.push(Released(*new_key));
-        events.push(Pressed(*new_key));
+        if !is_locking_key(new_key) {
+          events.push(Released(*new_key));
+          events.push(Pressed(*new_key));
+        }
+      }
+      else if state.pinned_passthrough_keys.contains(new_key) && state.pass_through_keys.contains(new_key) {
+        // Already held, and stays pass-through
       }
       else {
         if state.pass_through_keys.contains(new_key) {
-          events.push(Released(*new_key));
-          events.push(Pressed(*new_key));
+          if !is_locking_key(new_key) {
+            events.push(Released(*new_key));
+            events.push(Pressed(*new_key));
+          }
           state.pass_through_keys.retain(|k2| k2 != new_key);
           state.mapped_output_keys.push(*new_key);
         }
@@ -310,7 +653,47 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
     state.absorbing_trigger = Some(*new_key);
   }
   
-  state.active_mappings.push(m.clone());
+  match &m.layer {
+    None => (),
+    Some(LayerAction::Push(layer)) => {
+      state.layer_stack.push(*layer);
+    },
+    Some(LayerAction::Pop) => {
+      state.layer_stack.pop();
+    },
+    Some(LayerAction::OneShot(layer)) => {
+      state.one_shot_layer = Some(*layer);
+    }
+  };
+  
+  if m.load_layout.is_some() {
+    state.pending_layout = m.load_layout;
+  }
+  
+  if m.silent_release {
+    // Release the outputs now, unless another mapping still holds them,
+    // and keep only the trigger so its release is absorbed. Keys taken
+    // over from pass-through go back if they are still physically held.
+    for k in m.to.iter().rev() {
+      let still_used = state.active_mappings.iter().any(|m2| m2.to.contains(k));
+      if adopted.contains(k) && state.input_pressed_keys.contains(k) {
+        if state.mapped_output_keys.contains(k) {
+          state.mapped_output_keys.retain(|k2| k2 != k);
+          if !state.pass_through_keys.contains(k) {
+            state.pass_through_keys.push(*k);
+          }
+        }
+      }
+      else if state.mapped_output_keys.contains(k) && !still_used {
+        events.push(Released(*k));
+        state.mapped_output_keys.retain(|k2| k2 != k);
+      }
+    }
+    state.active_mappings.push(Mapping { to: vec![], ..m.clone() });
+  }
+  else {
+    state.active_mappings.push(m.clone());
+  }
   
   let mut res = StepResult {
     events,
@@ -345,6 +728,136 @@ fn add_new_mapping(state: &mut State, new_key: &KeyCode, m: &Mapping) -> StepRes
   res
 }
 
+// What `m` sends if it fires now: `to`, or the progressive output it has
+// reached
+fn next_output<'a>(state: &State, m: &'a Mapping) -> &'a Vec<KeyCode> {
+  if m.progressive_outputs.is_empty() {
+    &m.to
+  }
+  else {
+    let n = state.progress.get(&m.from).cloned().unwrap_or(0);
+    &m.progressive_outputs[n.min(m.progressive_outputs.len() - 1)]
+  }
+}
+
+// Sends `m.to` as a tap with nothing else held. Keys that were held
+// before are released first and pressed again afterwards, so a macro key
+// that was already held ends up held, not pressed twice.
+fn add_clear_first_mapping(state: &mut State, m: &Mapping) -> StepResult {
+  let mut events: Vec<Event> = Vec::new();
+  
+  // Pass-through keys that are part of the trigger are used up by it
+  let mut consumed: Vec<KeyCode> = Vec::new();
+  state.pass_through_keys.retain(|k| {
+    if m.from.contains(k) {
+      consumed.push(*k);
+      false
+    }
+    else {
+      true
+    }
+  });
+  
+  let mut held = state.mapped_output_keys.clone();
+  held.extend(state.pass_through_keys.iter());
+  
+  for k in consumed.iter().rev() {
+    events.push(Released(*k));
+  }
+  for k in held.iter().rev() {
+    events.push(Released(*k));
+  }
+  for k in &m.to {
+    events.push(Pressed(*k));
+  }
+  for k in m.to.iter().rev() {
+    events.push(Released(*k));
+  }
+  for k in &held {
+    events.push(Pressed(*k));
+  }
+  
+  // Track the trigger so its release is absorbed, but with nothing to
+  // hold down
+  state.active_mappings.push(Mapping { to: vec![], ..m.clone() });
+  
+  StepResult {
+    events,
+    repeat: ResultingRepeat::Disabled
+  }
+}
+
+// Adjusts the events for `input` for latched modifiers. A tapped latch
+// modifier's release is held back, and the latched modifiers are
+// released after the next press that sends a non-modifier key. Swallowed
+// presses, such as a layer key, leave them latched.
+fn update_latches(state: &mut State, input: Event, events: &mut Vec<Event>) {
+  match input {
+    Pressed(k) => {
+      let was_latched = state.latched.contains(&k);
+      let taken = take_over_latches(state, events);
+      if was_latched {
+        // Pressed again: it is already down on the output, and from now on
+        // it is an ordinary held key. If it is mapped to something else,
+        // the latched press is let go instead.
+        if !taken.contains(&k) {
+          state.latched.retain(|&k2| k2 != k);
+          events.push(Released(k));
+        }
+        return;
+      }
+      
+      state.latch_candidate = {
+        if state.latch_modifiers.contains(&k) {
+          Some(k)
+        }
+        else {
+          None
+        }
+      };
+      
+      let sends_action_key = events.iter().any(|ev| matches!(ev, Pressed(k2) if is_action_key(k2)));
+      if sends_action_key {
+        for l in state.latched.drain(..).rev() {
+          events.push(Released(l));
+        }
+      }
+    },
+    Released(k) => {
+      take_over_latches(state, events);
+      
+      // Only the final release is held back, in case the key is also
+      // tapped on the way
+      if state.latch_candidate == Some(k) {
+        state.latch_candidate = None;
+        if let Some(i) = events.iter().rposition(|ev| *ev == Pressed(k) || *ev == Released(k)) {
+          if events[i] == Released(k) {
+            events.remove(i);
+            state.latched.push(k);
+          }
+        }
+      }
+    }
+  }
+}
+
+// A latched key that the events go on to use is handed over to them: if
+// they press it first, that press is dropped since it is already down.
+// Returns the keys that were handed over.
+fn take_over_latches(state: &mut State, events: &mut Vec<Event>) -> Vec<KeyCode> {
+  let mut taken: Vec<KeyCode> = Vec::new();
+  for l in state.latched.clone() {
+    if let Some(i) = events.iter().position(|ev| *ev == Pressed(l) || *ev == Released(l)) {
+      if events[i] == Pressed(l) {
+        events.remove(i);
+      }
+      state.latched.retain(|&k2| k2 != l);
+      taken.push(l);
+    }
+  }
+  taken
+}
+
 fn release_all_action_keys(state: &mut State) -> Vec<Event> {
   let mut to_release: Vec<KeyCode> = Vec::new();
   
@@ -414,6 +927,25 @@ fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
   state.mapped_absorbed_keys.retain(|k2| *k2 != k);
   state.repeating_trigger = None;
   
+  // Releases from mappings cleared by this key come before its own output
+  for i in (0 .. state.active_mappings.len()).rev() {
+    let m = &state.active_mappings[i];
+    if m.clear_on_next_key && !m.from.contains(&k) {
+      let removed_key = final_key(&m.from);
+      res.events.append(&mut remove_mapping(state, i, removed_key));
+    }
+  }
+  
+  // A one-shot layer is used up by the next non-modifier key
+  let one_shot_layer = {
+    if is_action_key(&k) {
+      state.one_shot_layer.take()
+    }
+    else {
+      state.one_shot_layer
+    }
+  };
+  
   if let Some(mappings) = mappings.get(&k) {
     let should_absorb = {
       match &state.absorbing_trigger {
@@ -431,32 +963,45 @@ fn newly_press(mapper: &mut Mapper, k: KeyCode) -> StepResult {
       }
     };
     
-    for mapping in mappings.iter().rev() {
-      if is_supported(&mapping.from, &state.input_pressed_keys, &absorbed_keys, &k) {
-        res.append(add_new_mapping(&mut state, &k, &mapping));
-        any_hit = true;
-        break;
-      }
+    // The layer on top of the stack, and any one-shot layer, count as held
+    let pressed_keys = {
+      let mut pressed_keys = state.input_pressed_keys.clone();
+      pressed_keys.extend(state.layer_stack.last());
+      pressed_keys.extend(one_shot_layer);
+      pressed_keys
+    };
+    
+    let pressed_keys: Vec<KeyCode> = pressed_keys.into_iter()
+      .filter(|k2| !absorbed_keys.contains(k2))
+      .collect();
+    
+    if let Some(i) = select_mapping(mappings, &pressed_keys, k) {
+      let mapping = &mappings[i];
+      res.append(add_new_mapping(state, &k, mapping));
+      any_hit = true;
     }
   }
   
   if !any_hit {
-    for m in &state.active_mappings {
-      if m.from.contains(&k) {
-        any_hit = true;
-        break;
-      }
-      else if m.to.contains(&k) {
-        any_hit = true;
-        break;
+    if state.active_mappings.iter().any(|m| m.from.contains(&k)) {
+      any_hit = true;
+    }
+    else if state.mapped_output_keys.contains(&k) {
+      // The key is already held as some mapping's output, so it can't be
+      // pressed again, but it should still type. It stays with the
+      // mapping, which hands it over to pass-through when it ends.
+      if is_action_key(&k) && !is_locking_key(&k) {
+        res.events.push(Released(k));
+        res.events.push(Pressed(k));
       }
+      any_hit = true;
     }
   }
   
   if !any_hit {
     if !state.pass_through_keys.contains(&k) {
       if is_action_key(&k) {
-        res.events.append(&mut release_action_mappings(&mut state));
+        res.events.append(&mut release_action_mappings(&mut state, &[]));
         res.events.append(&mut release_absorbed_keys(&mut state));
       }
       
@@ -502,7 +1047,9 @@ fn remove_mapping(state: &mut State, i: usize, removed_key: KeyCode) -> Vec<Even
           }
         }
         if !still_shadowed {
-          pass_through_keys.push(k);
+          if !pass_through_keys.contains(&k) {
+            pass_through_keys.push(k);
+          }
         }
         else {
           res.push(Released(k));
@@ -517,6 +1064,51 @@ fn remove_mapping(state: &mut State, i: usize, removed_key: KeyCode) -> Vec<Even
       state.mapped_output_keys.remove(mapped_output_i);
     }
   }
+  
+  // Modifiers this mapping suppressed come back if another mapping still
+  // holds them or they are still physically held, unless another mapping
+  // that ignores extra modifiers is still active
+  let from = active_mappings[i].from.clone();
+  for s in 0 .. state.suppressed_modifiers.len() {
+    let (owner, k) = &state.suppressed_modifiers[s];
+    if *owner != from {
+      continue;
+    }
+    let k = *k;
+    let suppressing = (0 .. active_mappings.len()).find(|&j| {
+      j != i && active_mappings[j].ignore_extra_modifiers && !active_mappings[j].to.contains(&k)
+    });
+    let held = pass_through_keys.contains(&k) || state.mapped_output_keys.contains(&k);
+    if let Some(j) = suppressing {
+      state.suppressed_modifiers[s].0 = active_mappings[j].from.clone();
+    }
+    else if held {
+      // Already pressed again in the meantime
+    }
+    else if (0 .. active_mappings.len()).any(|j| {
+      // Mappings ending on the same release don't count
+      j != i && active_mappings[j].to.contains(&k) && !active_mappings[j].from.contains(&removed_key)
+    }) {
+      res.push(Pressed(k));
+      state.mapped_output_keys.push(k);
+    }
+    else if input_pressed_keys.contains(&k) && k != removed_key {
+      res.push(Pressed(k));
+      pass_through_keys.push(k);
+    }
+  }
+  state.suppressed_modifiers.retain(|(owner, _)| *owner != from);
+  
+  if let Some(seq) = &active_mappings[i].release_override {
+    // Keys still held on the output are left alone so nothing gets stuck
+    // or released from under another mapping
+    let mapped_output_keys = &state.mapped_output_keys;
+    let tapped: Vec<KeyCode> = seq.iter().cloned()
+      .filter(|k| !mapped_output_keys.contains(k) && !pass_through_keys.contains(k))
+      .collect();
+    res.extend(tapped.iter().map(|k| Pressed(*k)));
+    res.extend(tapped.iter().rev().map(|k| Released(*k)));
+  }
     
   active_mappings.remove(i);
   
@@ -548,6 +1140,8 @@ fn newly_release(mapper: &mut Mapper, k: KeyCode) -> StepResult {
     old_key != k
   });
   
+  state.progress.retain(|from, _| !from[.. from.len() - 1].contains(&k));
+  
   let repeat = ResultingRepeat::Disabled;
   
   StepResult { events, repeat }
@@ -559,12 +1153,30 @@ mod tests {
   use KeyCode::*;
   use std::default::Default;
   
+  // Feeds each input of `script` through a fresh mapper for `layout` and
+  // panics, naming the step, at the first output that differs from what the
+  // script expects.
+  fn assert_transform(layout: &Layout, script: &[(Event, Vec<Event>)]) {
+    let mut mapper = Mapper::for_layout(layout);
+    
+    for (i, (input, expected)) in script.iter().enumerate() {
+      let actual = mapper.step(*input).events;
+      if &actual != expected {
+        panic!(
+          "Step {} ({:?}) produced the wrong output\n  expected: {:?}\n    actual: {:?}",
+          i, input, expected, actual
+        );
+      }
+    }
+  }
+  
   #[test]
   fn test_most_basic() {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
@@ -575,7 +1187,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
@@ -593,7 +1206,8 @@ mod tests {
         Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
         Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
         Mapping { from: vec![CAPSLOCK, U], to: vec![EQUAL], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     let empty: Vec<Event> = Vec::new();
@@ -616,7 +1230,8 @@ mod tests {
         Mapping { from: vec![CAPSLOCK, N], to: vec![LEFTSHIFT, K1], ..Default::default() },
         Mapping { from: vec![TAB, M], to: vec![PAGEDOWN], ..Default::default() },
         Mapping { from: vec![TAB, N], to: vec![LEFTCTRL, LEFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     let mut mapper = Mapper::for_layout(&layout);
     
@@ -640,7 +1255,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Normal, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -657,7 +1273,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Normal, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -669,13 +1286,49 @@ mod tests {
     assert_eq!(StepResult { events: vec![Released(B)], repeat: ResultingRepeat::Disabled }, mapper.step(Released(B)));
   }
   
+  #[test]
+  fn no_repeat_assert_transform_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
+        Mapping { from: vec![B], to: vec![B], repeat: Repeat::Normal, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(A), vec![Pressed(A), Released(A)]),
+      (Released(A), vec![]),
+      (Pressed(B), vec![Pressed(B)]),
+      (Released(B), vec![Released(B)]),
+    ]);
+  }
+  
+  #[test]
+  #[should_panic(expected = "Step 1")]
+  fn assert_transform_mismatch_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(A), vec![Pressed(B)]),
+      (Released(A), vec![Released(A)]),
+    ]);
+  }
+  
   #[test]
   fn custom_repeat_test_1() {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -693,7 +1346,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![LEFTCTRL, C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -711,7 +1365,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![C], repeat: Repeat::Normal, ..Default::default() },
         Mapping { from: vec![B], to: vec![D], repeat: Repeat::Special { keys: vec![E], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -738,7 +1393,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![LEFTSHIFT, A], to: vec![LEFTSHIFT, A], absorbing: vec![LEFTSHIFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -754,7 +1410,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![LEFTSHIFT, A], to: vec![LEFTSHIFT, A], absorbing: vec![LEFTSHIFT], ..Default::default() },
         Mapping { from: vec![LEFTSHIFT, B], to: vec![LEFTSHIFT, B], absorbing: vec![LEFTSHIFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -771,7 +1428,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![Z], to: vec![APOSTROPHE], ..Default::default() },
         Mapping { from: vec![RIGHTSHIFT, Z], to: vec![LEFTSHIFT, APOSTROPHE], absorbing: vec![RIGHTSHIFT], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -789,7 +1447,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
         Mapping { from: vec![C], to: vec![D], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -805,7 +1464,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![LEFTSHIFT, B], ..Default::default() },
         Mapping { from: vec![C], to: vec![D], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut mapper = Mapper::for_layout(&layout);
@@ -813,5 +1473,1378 @@ mod tests {
     assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(B)], mapper.step(Pressed(A)).events);
     assert_eq!(vec![Released(B), Released(LEFTSHIFT), Pressed(D)], mapper.step(Pressed(C)).events);
   }
+  
+  #[test]
+  fn swap_layout_test_1() {
+    let layered = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let plain = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let layered = Arc::new(make_hashed_layout(&layered));
+    let plain = Arc::new(make_hashed_layout(&plain));
+    
+    let mut mapper = Mapper::for_hashed_layout(Arc::clone(&layered));
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(LEFTSHIFT), Pressed(EQUAL)], mapper.step(Pressed(M)).events);
+    assert_eq!(vec![Released(EQUAL), Released(LEFTSHIFT)], mapper.swap_layout(Arc::clone(&plain)));
+    assert_eq!(empty, mapper.step(Released(M)).events);
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(B)], mapper.swap_layout(Arc::clone(&layered)));
+    assert_eq!(empty, mapper.step(Released(A)).events);
+  }
+  
+  #[test]
+  fn swap_layout_keeps_shared_mappings() {
+    let layout_1 = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+        Mapping { from: vec![C], to: vec![D], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let layout_2 = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![B], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    let mut mapper = Mapper::for_layout(&layout_1);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(B)], mapper.step(Pressed(A)).events);
+    assert_eq!(empty, mapper.swap_layout(Arc::new(make_hashed_layout(&layout_2))));
+    assert_eq!(vec![Released(B)], mapper.step(Released(A)).events);
+  }
+  
+  #[test]
+  fn locking_key_not_retriggered_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![A], to: vec![CAPSLOCK], ..Default::default() },
+        Mapping { from: vec![B], to: vec![CAPSLOCK], ..Default::default() },
+        Mapping { from: vec![C], to: vec![D], ..Default::default() },
+        Mapping { from: vec![E], to: vec![D], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    // Passed-through lock key adopted by a mapping
+    assert_eq!(vec![Pressed(CAPSLOCK)], mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(empty, mapper.step(Pressed(A)).events);
+    assert_eq!(empty, mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(CAPSLOCK)], mapper.step(Released(CAPSLOCK)).events);
+    
+    // Lock key already produced by another mapping
+    assert_eq!(vec![Pressed(CAPSLOCK)], mapper.step(Pressed(A)).events);
+    assert_eq!(empty, mapper.step(Pressed(B)).events);
+    assert_eq!(empty, mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(CAPSLOCK)], mapper.step(Released(B)).events);
+    
+    // Other keys are still re-triggered
+    assert_eq!(vec![Pressed(D)], mapper.step(Pressed(C)).events);
+    assert_eq!(vec![Released(D), Pressed(D)], mapper.step(Pressed(E)).events);
+  }
+  
+  #[test]
+  fn layer_stack_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![F1], to: vec![], layer: Some(LayerAction::Push(CAPSLOCK)), ..Default::default() },
+        Mapping { from: vec![F2], to: vec![], layer: Some(LayerAction::Push(TAB)), ..Default::default() },
+        Mapping { from: vec![F3], to: vec![], layer: Some(LayerAction::Pop), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![TAB, J], to: vec![DOWN], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+    
+    // Go up one layer
+    assert_eq!(empty, mapper.step(Pressed(F1)).events);
+    assert_eq!(empty, mapper.step(Released(F1)).events);
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    
+    // And another
+    assert_eq!(empty, mapper.step(Pressed(F2)).events);
+    assert_eq!(empty, mapper.step(Released(F2)).events);
+    assert_eq!(vec![Pressed(DOWN)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(DOWN)], mapper.step(Released(J)).events);
+    
+    // Back down to the first
+    assert_eq!(empty, mapper.step(Pressed(F3)).events);
+    assert_eq!(empty, mapper.step(Released(F3)).events);
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    
+    // And back to the base
+    assert_eq!(empty, mapper.step(Pressed(F3)).events);
+    assert_eq!(empty, mapper.step(Released(F3)).events);
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+  }
+  
+  #[test]
+  fn empty_mapping_disable_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![INSERT], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    let empty: Vec<Event> = Vec::new();
+    
+    // Used as a layer
+    assert_eq!(empty, mapper.step(Pressed(CAPSLOCK)).events);
+    assert_eq!(vec![Pressed(LEFT)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(LEFT)], mapper.step(Released(J)).events);
+    assert_eq!(empty, mapper.step(Released(CAPSLOCK)).events);
+    
+    // Just disabled: emits nothing and doesn't affect other keys
+    assert_eq!(empty, mapper.step(Pressed(INSERT)).events);
+    assert_eq!(vec![Pressed(J)], mapper.step(Pressed(J)).events);
+    assert_eq!(vec![Released(J)], mapper.step(Released(J)).events);
+    assert_eq!(vec![Pressed(LEFTSHIFT)], mapper.step(Pressed(LEFTSHIFT)).events);
+    assert_eq!(vec![Pressed(A)], mapper.step(Pressed(A)).events);
+    assert_eq!(vec![Released(A)], mapper.step(Released(A)).events);
+    assert_eq!(vec![Released(LEFTSHIFT)], mapper.step(Released(LEFTSHIFT)).events);
+    assert_eq!(empty, mapper.step(Released(INSERT)).events);
+  }
+  
+  #[test]
+  fn declaration_order_priority_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT, J], to: vec![LEFTSHIFT, K], ..Default::default() },
+        Mapping { from: vec![TAB], to: vec![], ..Default::default() },
+        Mapping { from: vec![TAB, J], to: vec![DOWN], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(TAB), vec![]),
+      (Pressed(J), vec![Pressed(DOWN)]),
+    ]);
+  }
+  
+  #[test]
+  fn clear_first_test_1() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![F1], to: vec![LEFTCTRL, C], clear_first: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(F1), vec![Released(LEFTSHIFT), Pressed(LEFTCTRL), Pressed(C), Released(C), Released(LEFTCTRL), Pressed(LEFTSHIFT)]),
+      (Released(F1), vec![]),
+      (Released(LEFTSHIFT), vec![Released(LEFTSHIFT)]),
+    ]);
+  }
+  
+  #[test]
+  fn clear_first_overlap_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![F1], to: vec![LEFTCTRL, C], clear_first: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Pressed(F1), vec![Released(LEFTCTRL), Pressed(LEFTCTRL), Pressed(C), Released(C), Released(LEFTCTRL), Pressed(LEFTCTRL)]),
+      (Released(F1), vec![]),
+      (Released(LEFTCTRL), vec![Released(LEFTCTRL)]),
+    ]);
+  }
+  
+  #[test]
+  fn ignore_extra_modifiers_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTMETA, P], to: vec![F13], ignore_extra_modifiers: true, ..Default::default() },
+        Mapping { from: vec![LEFTMETA, O], to: vec![F14], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTMETA), vec![Pressed(LEFTMETA)]),
+      (Pressed(P), vec![Released(LEFTMETA), Pressed(F13)]),
+      (Released(P), vec![Released(F13)]),
+      (Released(LEFTMETA), vec![]),
+    ]);
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(LEFTMETA), vec![Pressed(LEFTMETA)]),
+      (Pressed(P), vec![Released(LEFTSHIFT), Released(LEFTMETA), Pressed(F13)]),
+      // Shift comes back once the mapping ends, since it is still held
+      (Released(P), vec![Released(F13), Pressed(LEFTSHIFT)]),
+      (Pressed(A), vec![Pressed(A)]),
+      (Released(A), vec![Released(A)]),
+      (Released(LEFTMETA), vec![]),
+      (Released(LEFTSHIFT), vec![Released(LEFTSHIFT)]),
+    ]);
+    
+    // Released during the mapping, it stays released
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(LEFTMETA), vec![Pressed(LEFTMETA)]),
+      (Pressed(P), vec![Released(LEFTSHIFT), Released(LEFTMETA), Pressed(F13)]),
+      (Released(LEFTSHIFT), vec![]),
+      (Released(P), vec![Released(F13)]),
+      (Pressed(A), vec![Pressed(A)]),
+    ]);
+    
+    // Without the flag the extra Shift stays held
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(LEFTMETA), vec![Pressed(LEFTMETA)]),
+      (Pressed(O), vec![Released(LEFTMETA), Pressed(F14)]),
+    ]);
+  }
+  
+  #[test]
+  fn debug_state_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    mapper.step(Pressed(LEFTSHIFT));
+    mapper.step(Pressed(CAPSLOCK));
+    mapper.step(Pressed(J));
+    
+    let golden = r#"{
+  "input_pressed_keys": [
+    "LEFTSHIFT",
+    "CAPSLOCK",
+    "J"
+  ],
+  "active_mappings": [
+    {
+      "from": [
+        "CAPSLOCK"
+      ],
+      "to": []
+    },
+    {
+      "from": [
+        "CAPSLOCK",
+        "J"
+      ],
+      "to": [
+        "LEFT"
+      ]
+    }
+  ],
+  "pass_through_keys": [
+    "LEFTSHIFT"
+  ],
+  "mapped_output_keys": [
+    "LEFT"
+  ],
+  "layer_stack": []
+}"#;
+    assert_eq!(golden, serde_json::to_string_pretty(&mapper.debug_state()).unwrap());
+  }
+
+  
+  #[test]
+  fn coalesce_modifier_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, K1], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, U], to: vec![LEFTSHIFT, K2], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(M), vec![Pressed(LEFTSHIFT), Pressed(K1)]),
+      (Pressed(U), vec![Released(K1), Pressed(K2)]),
+      (Released(M), vec![]),
+      (Released(U), vec![Released(K2), Released(LEFTSHIFT)]),
+    ]);
+  }
+  
+  #[test]
+  fn one_shot_layer_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], layer: Some(LayerAction::OneShot(CAPSLOCK)), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    // Fires in the layer, then clears
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Released(CAPSLOCK), vec![]),
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(J), vec![Pressed(LEFT)]),
+      (Released(J), vec![Released(LEFT)]),
+      (Pressed(J), vec![Pressed(J)]),
+      (Released(J), vec![Released(J)]),
+    ]);
+    
+    // A key outside the layer clears it and passes through
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Released(CAPSLOCK), vec![]),
+      (Pressed(K), vec![Pressed(K)]),
+      (Released(K), vec![Released(K)]),
+      (Pressed(J), vec![Pressed(J)]),
+    ]);
+    
+    // Holding the layer key still works as usual
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(LEFT)]),
+      (Released(J), vec![Released(LEFT)]),
+      (Pressed(J), vec![Pressed(LEFT)]),
+      (Released(J), vec![Released(LEFT)]),
+      (Released(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(J)]),
+    ]);
+  }
+  
+  #[test]
+  fn progressive_outputs_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping {
+          from: vec![CAPSLOCK, J],
+          to: vec![],
+          progressive_outputs: vec![vec![HOME], vec![LEFTCTRL, LEFT]],
+          ..Default::default()
+        },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(HOME)]),
+      (Released(J), vec![Released(HOME)]),
+      (Pressed(J), vec![Pressed(LEFTCTRL), Pressed(LEFT)]),
+      (Released(J), vec![Released(LEFT), Released(LEFTCTRL)]),
+      (Pressed(J), vec![Pressed(LEFTCTRL), Pressed(LEFT)]),
+      (Released(J), vec![Released(LEFT), Released(LEFTCTRL)]),
+      (Released(CAPSLOCK), vec![]),
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(HOME)]),
+    ]);
+  }
+  
+  #[test]
+  fn ordered_trigger_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTCTRL, LEFTALT, K], to: vec![F13], ordered: true, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Pressed(LEFTALT), vec![Pressed(LEFTALT)]),
+      (Pressed(K), vec![Released(LEFTCTRL), Released(LEFTALT), Pressed(F13)]),
+    ]);
+    
+    assert_transform(&layout, &[
+      (Pressed(LEFTALT), vec![Pressed(LEFTALT)]),
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Pressed(K), vec![Pressed(K)]),
+    ]);
+  }
+  
+  #[test]
+  fn lone_modifier_tap_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, U], to: vec![EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, N], to: vec![LEFTCTRL, Z], repeat: Repeat::Disabled, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    // Tapped in a held layer, before and during a mapping
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Released(LEFTSHIFT), vec![Released(LEFTSHIFT)]),
+      (Pressed(U), vec![Pressed(EQUAL)]),
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Released(LEFTSHIFT), vec![Released(LEFTSHIFT)]),
+      (Released(U), vec![Released(EQUAL)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+    
+    // The other Shift, while a mapping holds LEFTSHIFT
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(M), vec![Pressed(LEFTSHIFT), Pressed(EQUAL)]),
+      (Pressed(RIGHTSHIFT), vec![Pressed(RIGHTSHIFT)]),
+      (Released(RIGHTSHIFT), vec![Released(RIGHTSHIFT)]),
+      (Released(M), vec![Released(EQUAL), Released(LEFTSHIFT)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+    
+    // While a no-repeat mapping has released its action key
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(N), vec![Pressed(LEFTCTRL), Pressed(Z), Released(Z)]),
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Released(LEFTSHIFT), vec![Released(LEFTSHIFT)]),
+      (Released(N), vec![Released(LEFTCTRL)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+    
+    // Held across a mapping that adopts it: released exactly once, when
+    // the mapping ends
+    assert_transform(&layout, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(M), vec![Pressed(EQUAL)]),
+      (Released(LEFTSHIFT), vec![]),
+      (Released(M), vec![Released(EQUAL), Released(LEFTSHIFT)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn identical_outputs_retrigger_test() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, H], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    // Rolling from one to the other
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(LEFT)]),
+      (Pressed(H), vec![Released(LEFT), Pressed(LEFT)]),
+      (Released(J), vec![]),
+      (Released(H), vec![Released(LEFT)]),
+    ]);
+    
+    // One after the other
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(LEFT)]),
+      (Released(J), vec![Released(LEFT)]),
+      (Pressed(H), vec![Pressed(LEFT)]),
+      (Released(H), vec![Released(LEFT)]),
+    ]);
+    
+    // The same output already held as a pass-through key
+    assert_transform(&layout, &[
+      (Pressed(LEFT), vec![Pressed(LEFT)]),
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Released(LEFT), Pressed(LEFT)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_silent_release() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFTCTRL, C], silent_release: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(LEFTCTRL), Pressed(C), Released(C), Released(LEFTCTRL)]),
+      (Released(J), vec![]),
+      (Pressed(J), vec![Pressed(LEFTCTRL), Pressed(C), Released(C), Released(LEFTCTRL)]),
+      (Released(J), vec![]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn test_press_key_held_as_output() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(LEFT)]),
+      (Pressed(LEFT), vec![Released(LEFT), Pressed(LEFT)]),
+      (Released(J), vec![]),
+      (Released(LEFT), vec![Released(LEFT)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn test_pinned_passthrough_key() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![SPACE], ..Default::default() },
+      ],
+      pinned_passthrough_keys: vec![SPACE],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(mapper.step(Pressed(SPACE)).events, vec![Pressed(SPACE)]);
+    assert_eq!(mapper.step(Pressed(CAPSLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![]);
+    assert_eq!(mapper.step(Released(SPACE)).events, vec![Released(SPACE)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![]);
+    assert_eq!(mapper.step(Released(CAPSLOCK)).events, vec![]);
+  }
+  
+  #[test]
+  fn test_load_layout() {
+    let normal = Arc::new(make_hashed_layout(&Layout {
+      mappings: vec![
+        Mapping { from: vec![SCROLLLOCK], to: vec![], load_layout: Some(1), ..Default::default() },
+        Mapping { from: vec![J], to: vec![K], ..Default::default() },
+      ],
+      ..Default::default()
+    }));
+    let other = Arc::new(make_hashed_layout(&Layout {
+      mappings: vec![
+        Mapping { from: vec![SCROLLLOCK], to: vec![], load_layout: Some(0), ..Default::default() },
+        Mapping { from: vec![J], to: vec![L], ..Default::default() },
+      ],
+      ..Default::default()
+    }));
+    let mut mapper = Mapper::for_hashed_layout(normal.clone());
+    mapper.set_preloaded_layouts(vec![normal, other]);
+    
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(K)]);
+    // Outputs of mappings that the new layout lacks are released
+    assert_eq!(mapper.step(Pressed(SCROLLLOCK)).events, vec![Released(K)]);
+    assert_eq!(mapper.step(Released(SCROLLLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Released(J)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(L)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(L)]);
+    
+    assert_eq!(mapper.step(Pressed(SCROLLLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Released(SCROLLLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(K)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(K)]);
+  }
+  
+  fn permutations(keys: &[KeyCode]) -> Vec<Vec<KeyCode>> {
+    if keys.is_empty() {
+      return vec![vec![]];
+    }
+    let mut res = Vec::new();
+    for i in 0 .. keys.len() {
+      let mut rest = keys.to_vec();
+      let first = rest.remove(i);
+      for mut tail in permutations(&rest) {
+        tail.insert(0, first);
+        res.push(tail);
+      }
+    }
+    res
+  }
+  
+  #[test]
+  fn test_rollover_release_orders() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, K], to: vec![LEFTCTRL, RIGHT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let pressed = [LEFTSHIFT, CAPSLOCK, J, K, M];
+    
+    for order in permutations(&pressed) {
+      let mut mapper = Mapper::for_layout(&layout);
+      let mut held: Vec<KeyCode> = Vec::new();
+      let track = |held: &mut Vec<KeyCode>, events: Vec<Event>| {
+        for ev in events {
+          match ev {
+            Pressed(k) => {
+              assert!(!held.contains(&k), "{:?} pressed twice releasing {:?}", k, order);
+              held.push(k);
+            },
+            Released(k) => {
+              assert!(held.contains(&k), "{:?} released while not held releasing {:?}", k, order);
+              held.retain(|&k2| k2 != k);
+            }
+          }
+        }
+      };
+      
+      for k in &pressed {
+        track(&mut held, mapper.step(Pressed(*k)).events);
+      }
+      for (i, k) in order.iter().enumerate() {
+        track(&mut held, mapper.step(Released(*k)).events);
+        
+        // Shift is held while it is held physically or CAPSLOCK+M needs it
+        let still_down = &order[i + 1 ..];
+        let needs_shift = still_down.contains(&LEFTSHIFT)
+          || (still_down.contains(&CAPSLOCK) && still_down.contains(&M));
+        assert_eq!(held.contains(&LEFTSHIFT), needs_shift, "after releasing {:?}", &order[..= i]);
+      }
+      assert_eq!(held, vec![], "stuck releasing {:?}", order);
+    }
+  }
+  
+  #[test]
+  fn test_layer_key_holding_modifier() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![LEFTCTRL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ignore_extra_modifiers: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(CAPSLOCK), vec![Pressed(LEFTCTRL)]),
+      // Keys the layer doesn't map get Ctrl
+      (Pressed(C), vec![Pressed(C)]),
+      (Released(C), vec![Released(C)]),
+      // Mappings that ignore extra modifiers don't
+      (Pressed(J), vec![Released(LEFTCTRL), Pressed(LEFT)]),
+      // The layer's Ctrl is back once they end
+      (Released(J), vec![Released(LEFT), Pressed(LEFTCTRL)]),
+      (Pressed(C), vec![Pressed(C)]),
+      (Released(C), vec![Released(C)]),
+      (Released(CAPSLOCK), vec![Released(LEFTCTRL)]),
+    ]);
+    
+    // The layer ends while the mapping is still held
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![LEFTCTRL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ignore_extra_modifiers: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(CAPSLOCK), vec![Pressed(LEFTCTRL)]),
+      (Pressed(J), vec![Released(LEFTCTRL), Pressed(LEFT)]),
+      (Released(CAPSLOCK), vec![Released(LEFT)]),
+      (Released(J), vec![]),
+      (Pressed(C), vec![Pressed(C)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_reachable_outputs() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, U], to: vec![EQUAL], ..Default::default() },
+        Mapping { from: vec![TAB, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![LEFTSHIFT, U], to: vec![PAGEUP], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(reachable_outputs(&layout, &[CAPSLOCK]), vec![
+      (M, vec![LEFTSHIFT, EQUAL]),
+      (U, vec![EQUAL]),
+      (J, vec![J]),
+    ]);
+  }
+  
+  #[test]
+  fn test_release_ends_several_mappings() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFTSHIFT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, K], to: vec![LEFTSHIFT, LEFTALT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, L], to: vec![LEFTCTRL, LEFTALT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    assert_eq!(mapper.step(Pressed(CAPSLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(LEFTSHIFT)]);
+    assert_eq!(mapper.step(Pressed(K)).events, vec![Pressed(LEFTALT)]);
+    assert_eq!(mapper.step(Pressed(L)).events, vec![Pressed(LEFTCTRL)]);
+    
+    // All three mappings end at once; each output is released exactly once
+    let mut events = mapper.step(Released(CAPSLOCK)).events;
+    events.sort_by_key(|ev| format!("{:?}", ev));
+    assert_eq!(events, vec![Released(LEFTALT), Released(LEFTCTRL), Released(LEFTSHIFT)]);
+    assert_eq!(mapper.state.mapped_output_keys, vec![]);
+    assert!(mapper.state.active_mappings.is_empty());
+    
+    assert_eq!(mapper.step(Released(J)).events, vec![]);
+    assert_eq!(mapper.step(Released(K)).events, vec![]);
+    assert_eq!(mapper.step(Released(L)).events, vec![]);
+  }
+  
+  #[test]
+  fn test_chord_output_held_together() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![F5], to: vec![W, A], ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(F5), vec![Pressed(W), Pressed(A)]),
+      (Pressed(SPACE), vec![Pressed(SPACE)]),
+      (Released(SPACE), vec![Released(SPACE)]),
+      (Released(F5), vec![Released(A), Released(W)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_release_all_twice() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, K], to: vec![LEFTCTRL, RIGHT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    for k in [LEFTALT, CAPSLOCK, J, K, M, A] {
+      mapper.step(Pressed(k));
+    }
+    
+    assert!(!mapper.release_all().is_empty());
+    let state = &mapper.state;
+    assert!(state.input_pressed_keys.is_empty());
+    assert!(state.mapped_output_keys.is_empty());
+    assert!(state.pass_through_keys.is_empty());
+    assert!(state.active_mappings.is_empty());
+    assert_eq!(mapper.release_all(), vec![]);
+  }
+  
+  #[test]
+  fn test_identity_mapping_is_passthrough() {
+    let identity = Layout {
+      mappings: vec![Mapping { from: vec![A], to: vec![A], ..Default::default() }],
+      ..Default::default()
+    };
+    let empty = Layout { mappings: vec![], ..Default::default() };
+    let sequences = [
+      vec![Pressed(A), Released(A)],
+      vec![Pressed(LEFTSHIFT), Pressed(A), Released(LEFTSHIFT), Released(A)],
+      vec![Pressed(A), Pressed(B), Released(A), Pressed(A), Released(B), Released(A)],
+      vec![Pressed(A), Pressed(LEFTCTRL), Pressed(C), Released(C), Released(A), Released(LEFTCTRL)],
+    ];
+    
+    for seq in &sequences {
+      let mut mapped = Mapper::for_layout(&identity);
+      let mut plain = Mapper::for_layout(&empty);
+      for ev in seq {
+        assert_eq!(mapped.step(*ev).events, plain.step(*ev).events, "{:?} in {:?}", ev, seq);
+      }
+    }
+  }
+  
+  #[test]
+  fn test_layer_identity_mapping() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![J], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, K], to: vec![K], repeat: Repeat::Disabled, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(J)]),
+      (Released(J), vec![Released(J)]),
+      (Pressed(J), vec![Pressed(J)]),
+      // The layer ending hands J over to pass-through rather than
+      // releasing it
+      (Released(CAPSLOCK), vec![]),
+      (Released(J), vec![Released(J)]),
+    ]);
+    assert_transform(&layout, &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(K), vec![Pressed(K), Released(K)]),
+      (Released(K), vec![]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn test_bare_modifier_output() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![F13], to: vec![LEFTMETA], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_transform(&layout, &[
+      (Pressed(F13), vec![Pressed(LEFTMETA)]),
+      (Pressed(A), vec![Pressed(A)]),
+      (Released(A), vec![Released(A)]),
+      (Released(F13), vec![Released(LEFTMETA)]),
+    ]);
+    // Physical Meta held first, released last
+    assert_transform(&layout, &[
+      (Pressed(LEFTMETA), vec![Pressed(LEFTMETA)]),
+      (Pressed(F13), vec![]),
+      (Released(F13), vec![]),
+      (Released(LEFTMETA), vec![Released(LEFTMETA)]),
+    ]);
+    // Physical Meta pressed during the mapping, released after it
+    assert_transform(&layout, &[
+      (Pressed(F13), vec![Pressed(LEFTMETA)]),
+      (Pressed(LEFTMETA), vec![]),
+      (Released(F13), vec![]),
+      (Released(LEFTMETA), vec![Released(LEFTMETA)]),
+    ]);
+    // Physical Meta pressed and released during the mapping
+    assert_transform(&layout, &[
+      (Pressed(F13), vec![Pressed(LEFTMETA)]),
+      (Pressed(LEFTMETA), vec![]),
+      (Released(LEFTMETA), vec![]),
+      (Released(F13), vec![Released(LEFTMETA)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_fast_bypass() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, W], to: vec![UP], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      fast_bypass: vec![W, A, S, D],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(mapper.step(Pressed(CAPSLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(W)).events, vec![Pressed(W)]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(LEFT)]);
+    assert_eq!(mapper.step(Released(W)).events, vec![Released(W)]);
+    assert_eq!(mapper.step(Pressed(A)).events, vec![Pressed(A)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(LEFT)]);
+    
+    assert_eq!(mapper.state.input_pressed_keys, vec![CAPSLOCK]);
+    assert_eq!(mapper.state.pass_through_keys, vec![]);
+    assert_eq!(mapper.state.mapped_output_keys, vec![]);
+  }
+  
+  // Times W through a default layout with and without bypassing it. Run
+  // with `cargo test --release -- --ignored bench_fast_bypass --nocapture`.
+  #[test]
+  #[ignore]
+  fn bench_fast_bypass() {
+    use crate::default_fancy_layouts::DEFAULT_LAYOUTS;
+    use crate::layout_parsing_formatting::parse_layout_from_json;
+    use crate::fancy_layout_interpreting::convert;
+    use std::hint::black_box;
+    use std::time::Instant;
+    
+    let (_, json) = DEFAULT_LAYOUTS.iter().find(|(name, _)| *name == "super-dvorak").unwrap();
+    let layout = convert(&parse_layout_from_json(&serde_json::from_str(json).unwrap()).unwrap()).unwrap();
+    
+    let time = |bypass: bool| {
+      let fast_bypass = if bypass { vec![W] } else { vec![] };
+      let mut mapper = Mapper::for_layout(&Layout { fast_bypass, ..layout.clone() });
+      mapper.step(Pressed(LEFTSHIFT));
+      mapper.step(Pressed(CAPSLOCK));
+      
+      let start = Instant::now();
+      for _ in 0 .. 100000 {
+        black_box(mapper.step(Pressed(W)));
+        black_box(mapper.step(Released(W)));
+      }
+      start.elapsed()
+    };
+    
+    let stepped = time(false);
+    let bypassed = time(true);
+    println!("step: {:?}, bypass: {:?}", stepped, bypassed);
+    assert!(bypassed < stepped);
+  }
+  
+  #[test]
+  fn test_debug_dump_sequence() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      debug_dump_sequence: Some(vec![LEFTCTRL, F12]),
+      ..Default::default()
+    };
+    let dumps: Rc<RefCell<Vec<DebugState>>> = Rc::new(RefCell::new(Vec::new()));
+    let mut mapper = Mapper::for_layout(&layout);
+    let dumps2 = dumps.clone();
+    mapper.set_debug_dump_handler(Box::new(move |state| dumps2.borrow_mut().push(state)));
+    
+    assert_eq!(mapper.step(Pressed(CAPSLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(LEFT)]);
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Pressed(F12)).events, vec![]);
+    assert_eq!(mapper.step(Released(F12)).events, vec![]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![Released(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(LEFT)]);
+    
+    // F12 on its own is an ordinary key
+    assert_eq!(mapper.step(Pressed(F12)).events, vec![Pressed(F12)]);
+    assert_eq!(mapper.step(Released(F12)).events, vec![Released(F12)]);
+    
+    let dumps = dumps.borrow();
+    assert_eq!(dumps.len(), 1);
+    assert_eq!(dumps[0].input_pressed_keys, vec![CAPSLOCK, J, LEFTCTRL]);
+    assert_eq!(dumps[0].mapped_output_keys, vec![LEFT]);
+    assert_eq!(dumps[0].pass_through_keys, vec![LEFTCTRL]);
+  }
+  
+  #[test]
+  fn test_latch_modifiers() {
+    let mut mapper = Mapper::for_layout(&Layout { latch_modifiers: vec![LEFTCTRL, LEFTSHIFT], ..Default::default() });
+    
+    // Single latch
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(A)).events, vec![Pressed(A), Released(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(A)).events, vec![Released(A)]);
+    assert_eq!(mapper.step(Pressed(B)).events, vec![Pressed(B)]);
+    assert_eq!(mapper.step(Released(B)).events, vec![Released(B)]);
+    
+    // Stacked, with a modifier that doesn't latch in between
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(LEFTSHIFT)).events, vec![Pressed(LEFTSHIFT)]);
+    assert_eq!(mapper.step(Released(LEFTSHIFT)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(LEFTALT)).events, vec![Pressed(LEFTALT)]);
+    assert_eq!(mapper.step(Released(LEFTALT)).events, vec![Released(LEFTALT)]);
+    assert_eq!(mapper.step(Pressed(T)).events, vec![Pressed(T), Released(LEFTSHIFT), Released(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(T)).events, vec![Released(T)]);
+  }
+  
+  #[test]
+  fn test_latch_modifiers_clear() {
+    let mut mapper = Mapper::for_layout(&Layout { latch_modifiers: vec![LEFTCTRL], ..Default::default() });
+    
+    // Held through a key press, it is an ordinary modifier
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Pressed(C)).events, vec![Pressed(C)]);
+    assert_eq!(mapper.step(Released(C)).events, vec![Released(C)]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![Released(LEFTCTRL)]);
+    
+    // Tapping again unlatches
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![Released(LEFTCTRL)]);
+    assert_eq!(mapper.step(Pressed(C)).events, vec![Pressed(C)]);
+    assert_eq!(mapper.step(Released(C)).events, vec![Released(C)]);
+    
+    // Latched modifiers are released by release_all
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![]);
+    assert_eq!(mapper.release_all(), vec![Released(LEFTCTRL)]);
+  }
+  
+  #[test]
+  fn test_sticky_layer() {
+    let layout = |sticky_layer| Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], sticky_layer, ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, COMMA], to: vec![LEFTSHIFT, K8], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, DOT], to: vec![LEFTSHIFT, K9], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    
+    assert_transform(&layout(false), &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(M), vec![Pressed(LEFTSHIFT), Pressed(EQUAL)]),
+      (Pressed(COMMA), vec![Released(EQUAL), Pressed(K8)]),
+      (Released(M), vec![]),
+      (Released(COMMA), vec![Released(K8), Released(LEFTSHIFT)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+    
+    assert_transform(&layout(true), &[
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(M), vec![Pressed(LEFTSHIFT), Pressed(EQUAL)]),
+      (Pressed(COMMA), vec![Pressed(K8)]),
+      (Released(M), vec![Released(EQUAL)]),
+      (Pressed(DOT), vec![Pressed(K9)]),
+      (Released(COMMA), vec![Released(K8)]),
+      (Released(DOT), vec![Released(K9), Released(LEFTSHIFT)]),
+      (Released(CAPSLOCK), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn test_pass_through_keys_unique() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, K], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    let mut events: Vec<Event> = Vec::new();
+    for ev in [Pressed(CAPSLOCK), Pressed(J), Pressed(K), Pressed(LEFT), Released(J), Released(K), Released(CAPSLOCK)] {
+      events.append(&mut mapper.step(ev).events);
+      let count = mapper.state.pass_through_keys.iter().filter(|&&k| k == LEFT).count();
+      assert!(count <= 1, "LEFT is in pass_through_keys {} times after {:?}", count, ev);
+    }
+    events.append(&mut mapper.step(Released(LEFT)).events);
+    
+    assert_eq!(events.iter().filter(|&&ev| ev == Released(LEFT)).count(),
+      events.iter().filter(|&&ev| ev == Pressed(LEFT)).count());
+    assert_eq!(mapper.state.pass_through_keys, vec![]);
+  }
+  
+  // A small xorshift generator, so the property test below is repeatable
+  // without extra dependencies
+  struct Rng(u64);
+  
+  impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      (self.0 % n as u64) as usize
+    }
+    
+    fn pick_distinct(&mut self, pool: &[KeyCode], n: usize) -> Vec<KeyCode> {
+      let mut res: Vec<KeyCode> = Vec::new();
+      while res.len() < n {
+        let k = pool[self.below(pool.len())];
+        if !res.contains(&k) {
+          res.push(k);
+        }
+      }
+      res
+    }
+  }
+  
+  fn random_layout(rng: &mut Rng) -> Layout {
+    let inputs = [CAPSLOCK, TAB, A, B, J, K, LEFTSHIFT, LEFTCTRL];
+    let outputs = [LEFT, RIGHT, A, B, EQUAL, LEFTSHIFT, LEFTCTRL, LEFTALT];
+    
+    let n = 1 + rng.below(6);
+    let mappings = (0 .. n).map(|_| {
+      let from_len = 1 + rng.below(2);
+      let to_len = rng.below(3);
+      Mapping {
+        from: rng.pick_distinct(&inputs, from_len),
+        to: rng.pick_distinct(&outputs, to_len),
+        repeat: if rng.below(4) == 0 { Repeat::Disabled } else { Repeat::Normal },
+        ignore_extra_modifiers: rng.below(3) == 0,
+        ..Default::default()
+      }
+    }).collect();
+    
+    Layout { mappings, ..Default::default() }
+  }
+  
+  #[test]
+  fn test_random_layouts_stay_balanced() {
+    let keys = [CAPSLOCK, TAB, A, B, J, K, LEFTSHIFT, LEFTCTRL, X];
+    let mut rng = Rng(0x2545F4914F6CDD1D);
+    
+    for case in 0 .. 2000 {
+      let layout = random_layout(&mut rng);
+      let mut mapper = Mapper::for_layout(&layout);
+      let mut inputs: Vec<Event> = Vec::new();
+      let mut held: Vec<KeyCode> = Vec::new();
+      
+      let mut check = |inputs: &Vec<Event>, events: Vec<Event>| {
+        for ev in events {
+          match ev {
+            Pressed(k) => {
+              assert!(!held.contains(&k), "case {}: {:?} pressed twice\n{:?}\n{:?}", case, k, layout.mappings, inputs);
+              held.push(k);
+            },
+            Released(k) => {
+              assert!(held.contains(&k), "case {}: {:?} released while up\n{:?}\n{:?}", case, k, layout.mappings, inputs);
+              held.retain(|&k2| k2 != k);
+            }
+          }
+        }
+      };
+      
+      for _ in 0 .. 30 {
+        let k = keys[rng.below(keys.len())];
+        let ev = if rng.below(2) == 0 { Pressed(k) } else { Released(k) };
+        inputs.push(ev);
+        check(&inputs, mapper.step(ev).events);
+      }
+      check(&inputs, mapper.release_all());
+      assert_eq!(held, vec![], "case {}: stuck\n{:?}\n{:?}", case, layout.mappings, inputs);
+    }
+  }
+  
+  #[test]
+  fn test_shared_modifier_released_once() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![B, A], to: vec![LEFTSHIFT, A], ..Default::default() },
+        Mapping { from: vec![TAB], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(B), vec![Pressed(B)]),
+      (Pressed(A), vec![Released(B), Pressed(LEFTSHIFT), Pressed(A)]),
+      (Pressed(TAB), vec![Released(A), Pressed(EQUAL)]),
+      (Released(A), vec![]),
+      (Pressed(A), vec![Released(EQUAL), Pressed(A)]),
+      // Both mappings hold Shift, but it is only released once
+      (Pressed(J), vec![Released(LEFTSHIFT), Released(A), Pressed(J)]),
+      (Released(J), vec![Released(J)]),
+      (Released(A), vec![]),
+      (Released(B), vec![]),
+      (Released(TAB), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn test_clear_on_next_key() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![F5], to: vec![W, A], clear_on_next_key: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(F5), vec![Pressed(W), Pressed(A)]),
+      (Pressed(SPACE), vec![Released(A), Released(W), Pressed(SPACE)]),
+      (Released(F5), vec![]),
+      (Released(SPACE), vec![Released(SPACE)]),
+      (Pressed(F5), vec![Pressed(W), Pressed(A)]),
+      (Released(F5), vec![Released(A), Released(W)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_release_override() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![F13], to: vec![LEFTCTRL, F1], release_override: Some(vec![LEFTCTRL, F2]), ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(F13), vec![Pressed(LEFTCTRL), Pressed(F1)]),
+      (Released(F13), vec![
+        Released(F1), Released(LEFTCTRL),
+        Pressed(LEFTCTRL), Pressed(F2), Released(F2), Released(LEFTCTRL)
+      ]),
+      (Pressed(F13), vec![Pressed(LEFTCTRL), Pressed(F1)]),
+      (Released(F13), vec![
+        Released(F1), Released(LEFTCTRL),
+        Pressed(LEFTCTRL), Pressed(F2), Released(F2), Released(LEFTCTRL)
+      ]),
+    ]);
+  }
+  
+  #[test]
+  fn test_release_override_leaves_held_keys_alone() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![F13], to: vec![F1], release_override: Some(vec![LEFTCTRL, F2]), ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Pressed(F13), vec![Pressed(F1)]),
+      (Released(F13), vec![Released(F1), Pressed(F2), Released(F2)]),
+      (Released(LEFTCTRL), vec![Released(LEFTCTRL)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_progressive_outputs_in_reachable_outputs() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping {
+          from: vec![CAPSLOCK, J],
+          to: vec![],
+          progressive_outputs: vec![vec![HOME], vec![LEFTCTRL, HOME]],
+          ..Default::default()
+        },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(reachable_outputs(&layout, &[CAPSLOCK]), vec![(J, vec![HOME])]);
+  }
+  
+  #[test]
+  fn test_latch_modifiers_survive_layer_key() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      latch_modifiers: vec![LEFTSHIFT],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(mapper.step(Pressed(LEFTSHIFT)).events, vec![Pressed(LEFTSHIFT)]);
+    assert_eq!(mapper.step(Released(LEFTSHIFT)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(CAPSLOCK)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(LEFT), Released(LEFTSHIFT)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(LEFT)]);
+    assert_eq!(mapper.step(Released(CAPSLOCK)).events, vec![]);
+  }
+  
+  #[test]
+  fn test_silent_release_keeps_held_modifier() {
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFTCTRL, C], silent_release: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Pressed(CAPSLOCK), vec![]),
+      (Pressed(J), vec![Pressed(C), Released(C)]),
+      (Released(J), vec![]),
+      (Released(CAPSLOCK), vec![]),
+      // Ctrl is still held for keys typed afterwards
+      (Pressed(X), vec![Pressed(X)]),
+      (Released(X), vec![Released(X)]),
+      (Released(LEFTCTRL), vec![Released(LEFTCTRL)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_silent_release_of_absorbed_key() {
+    // A is absorbed by LEFTSHIFT, so K's mapping takes it over after its
+    // physical press has been forgotten. It must not go back to
+    // pass-through, where nothing would release it.
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![], absorbing: vec![A], ..Default::default() },
+        Mapping { from: vec![K], to: vec![A], silent_release: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(A), vec![Pressed(A)]),
+      (Pressed(LEFTSHIFT), vec![]),
+      (Pressed(K), vec![Released(A), Pressed(A), Released(A)]),
+      (Released(K), vec![]),
+      (Released(A), vec![]),
+      (Released(LEFTSHIFT), vec![]),
+    ]);
+  }
+  
+  #[test]
+  fn test_silent_release_when_absorbing_ends_another_mapping() {
+    // CAPSLOCK's mapping takes over the held Ctrl, then ends K's absorbed
+    // LEFTSHIFT mapping, which already hands Ctrl back to pass-through
+    assert_transform(&Layout {
+      mappings: vec![
+        Mapping { from: vec![LEFTSHIFT], to: vec![LEFTSHIFT], ..Default::default() },
+        Mapping { from: vec![K], to: vec![LEFT], absorbing: vec![LEFTSHIFT], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK], to: vec![LEFTCTRL, RIGHT], silent_release: true, ..Default::default() },
+      ],
+      ..Default::default()
+    }, &[
+      (Pressed(LEFTSHIFT), vec![Pressed(LEFTSHIFT)]),
+      (Pressed(K), vec![Pressed(LEFT)]),
+      (Released(K), vec![Released(LEFT)]),
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Pressed(CAPSLOCK), vec![Released(LEFTSHIFT), Pressed(RIGHT), Released(RIGHT)]),
+      (Released(CAPSLOCK), vec![]),
+      (Released(LEFTCTRL), vec![Released(LEFTCTRL)]),
+      (Pressed(LEFTCTRL), vec![Pressed(LEFTCTRL)]),
+      (Released(LEFTCTRL), vec![Released(LEFTCTRL)]),
+    ]);
+  }
+  
+  #[test]
+  fn test_latched_modifier_taken_over() {
+    // A mapping that outputs the latched key takes it over instead of
+    // pressing it a second time
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![K], to: vec![LEFTSHIFT], ..Default::default() },
+        Mapping { from: vec![A], to: vec![], clear_on_next_key: true, release_override: Some(vec![LEFTCTRL, LEFTALT]), ..Default::default() },
+      ],
+      latch_modifiers: vec![LEFTSHIFT, LEFTCTRL],
+      ..Default::default()
+    };
+    let mut mapper = Mapper::for_layout(&layout);
+    
+    assert_eq!(mapper.step(Pressed(LEFTSHIFT)).events, vec![Pressed(LEFTSHIFT)]);
+    assert_eq!(mapper.step(Released(LEFTSHIFT)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(K)).events, vec![]);
+    assert_eq!(mapper.step(Released(K)).events, vec![Released(LEFTSHIFT)]);
+    assert_eq!(mapper.step(Pressed(X)).events, vec![Pressed(X)]);
+    assert_eq!(mapper.step(Released(X)).events, vec![Released(X)]);
+    
+    // Pressing the latched key again also ends a mapping that taps it.
+    // The tap releases the latched press, and the new one stays down.
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![Pressed(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(A)).events, vec![]);
+    assert_eq!(mapper.step(Pressed(LEFTCTRL)).events, vec![
+      Pressed(LEFTALT), Released(LEFTALT), Released(LEFTCTRL), Pressed(LEFTCTRL)
+    ]);
+    assert_eq!(mapper.step(Released(LEFTCTRL)).events, vec![Released(LEFTCTRL)]);
+    assert_eq!(mapper.step(Released(A)).events, vec![]);
+  }
 }
 
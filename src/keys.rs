@@ -15,7 +15,50 @@ pub struct Mapping {
   #[serde(default = "normal_repeat")]
   pub repeat: Repeat,
   #[serde(default = "Vec::new")]
-  pub absorbing: Vec<KeyCode>
+  pub absorbing: Vec<KeyCode>,
+  #[serde(default)]
+  pub layer: Option<LayerAction>,
+  // Release everything held before sending `to` as a single tap, then
+  // press the held keys again.
+  #[serde(default)]
+  pub clear_first: bool,
+  // Releases any other held modifiers when the mapping fires, so e.g.
+  // Meta+P and Meta+Shift+P produce the same output.
+  #[serde(default)]
+  pub ignore_extra_modifiers: bool,
+  // If set, used instead of `to`: the first activation while the rest of
+  // the trigger stays held sends the first entry, the next the second, and
+  // so on, staying on the last. Releasing the rest of the trigger starts
+  // over.
+  #[serde(default)]
+  pub progressive_outputs: Vec<Vec<KeyCode>>,
+  // Only fire if the keys in `from` were pressed in the order listed
+  #[serde(default)]
+  pub ordered: bool,
+  // Send `to` as a tap when the mapping fires, so releasing the trigger
+  // emits nothing for it. For fire-and-forget macros.
+  #[serde(default)]
+  pub silent_release: bool,
+  // Switch to the layout with this index when the mapping fires. See
+  // `Layout::layouts`.
+  #[serde(default)]
+  pub load_layout: Option<usize>,
+  // Free-form categories for tooling, e.g. "navigation". The mapper
+  // ignores them.
+  #[serde(default)]
+  pub tags: Vec<String>,
+  // For a layer key's own mapping: while it is held, mappings in the
+  // layer don't release each other's outputs when another one fires
+  #[serde(default)]
+  pub sticky_layer: bool,
+  // End the mapping, releasing its outputs, when any key outside its
+  // trigger is pressed, even if the trigger is still held
+  #[serde(default)]
+  pub clear_on_next_key: bool,
+  // Tapped as a chord once the mapping's outputs have been released, e.g.
+  // a separate "mute" shortcut for a push-to-talk key
+  #[serde(default)]
+  pub release_override: Option<Vec<KeyCode>>
 }
 
 impl Default for Mapping {
@@ -24,7 +67,18 @@ impl Default for Mapping {
       from: vec![],
       to: vec![],
       repeat: Repeat::Normal,
-      absorbing: vec![]
+      absorbing: vec![],
+      layer: None,
+      clear_first: false,
+      ignore_extra_modifiers: false,
+      progressive_outputs: vec![],
+      ordered: false,
+      silent_release: false,
+      load_layout: None,
+      tags: vec![],
+      sticky_layer: false,
+      clear_on_next_key: false,
+      release_override: None
     }
   }
 }
@@ -40,12 +94,84 @@ pub enum Repeat {
   }
 }
 
+// Changes the stack of latched layers when a mapping fires. A layer is
+// named by its trigger key; while a layer is on top of the stack, mappings
+// match as if its trigger key were held. A one-shot layer only applies to
+// the next non-modifier key press, whether or not that key is in the layer.
+// Serialized the way layout files write it, e.g. {"push": "CAPSLOCK"}.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerAction {
+  Push(KeyCode),
+  Pop,
+  OneShot(KeyCode)
+}
+
 pub fn normal_repeat() -> Repeat {
   Repeat::Normal
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Layout {
-  pub mappings: Vec<Mapping>
+  pub mappings: Vec<Mapping>,
+  // Pressing the final key while holding the rest logs the mapper's state
+  // instead of mapping the key. For tracking down stuck keys in the field.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub debug_dump_sequence: Option<Vec<KeyCode>>,
+  // Other layouts that mappings can switch to with `load_layout`. This
+  // layout is index 0 and `layouts[i]` is index i + 1.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub layouts: Vec<Layout>,
+  // Keys that stay pass-through while held, even if a mapping starts
+  // typing the same key
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub pinned_passthrough_keys: Vec<KeyCode>,
+  // Keys whose events skip the mapper entirely. They can't be part of any
+  // mapping.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub fast_bypass: Vec<KeyCode>,
+  // Modifiers that stay held after a tap until the next non-modifier key
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub latch_modifiers: Vec<KeyCode>
+}
+
+// What `m` sends the first time it fires: the first of its
+// `progressive_outputs` if it has any, otherwise `to`
+pub fn first_output(m: &Mapping) -> &Vec<KeyCode> {
+  m.progressive_outputs.first().unwrap_or(&m.to)
+}
+
+// The mappings tagged with `tag`, in layout order
+pub fn mappings_with_tag<'a>(layout: &'a Layout, tag: &str) -> Vec<&'a Mapping> {
+  layout.mappings.iter().filter(|m| m.tags.iter().any(|t| t == tag)).collect()
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use KeyCode::*;
+
+  #[test]
+  fn test_mappings_with_tag() {
+    let nav = vec!["navigation".to_string()];
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], tags: nav.clone(), ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], tags: vec!["symbols".to_string()], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, L], to: vec![RIGHT], tags: nav.clone(), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let found: Vec<&Vec<KeyCode>> = mappings_with_tag(&layout, "navigation").iter().map(|m| &m.to).collect();
+    assert_eq!(found, vec![&vec![LEFT], &vec![RIGHT]]);
+    assert!(mappings_with_tag(&layout, "danger").is_empty());
+  }
+
+  #[test]
+  fn test_tags_round_trip() {
+    let m = Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], tags: vec!["navigation".to_string()], ..Default::default() };
+    let json = serde_json::to_string(&m).unwrap();
+    assert_eq!(serde_json::from_str::<Mapping>(&json).unwrap(), m);
+  }
+}
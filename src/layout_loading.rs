@@ -3,6 +3,10 @@
 
 use crate::keys::Layout;
 use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use Value::{Object, Array};
 
 fn convert_io_error<T>(whats_happening: &str, res: Result<T, std::io::Error>) -> Result<T, String> {
   match res {
@@ -19,9 +23,90 @@ fn convert_json_error<T>(whats_happening: &str, res: Result<T, serde_json::Error
 }
 
 pub fn load_layout_from_file(path: &str) -> Result<Layout, String> {
+  let mut root = load_json_with_includes(Path::new(path), &mut Vec::new())?;
+  
+  let layouts = match &mut root {
+    Object(root_values) => match root_values.remove("layouts") {
+      None => vec![],
+      Some(layouts_v) => load_layouts(Path::new(path), layouts_v)?
+    },
+    _ => vec![]
+  };
+  
+  let debug_dump_sequence = take_setting(path, &mut root, "debug_dump_sequence")?;
+  let pinned_passthrough_keys = take_setting(path, &mut root, "pinned_passthrough_keys")?;
+  let fast_bypass = take_setting(path, &mut root, "fast_bypass")?;
+  let latch_modifiers = take_setting(path, &mut root, "latch_modifiers")?;
+  
+  let layout = crate::fancy_layout_interpreting::convert(
+    &crate::layout_parsing_formatting::parse_layout_from_json(&root)?
+  )?;
+  
+  Ok(Layout { debug_dump_sequence, layouts, pinned_passthrough_keys, fast_bypass, latch_modifiers, ..layout })
+}
+
+// Settings that apply to the whole file rather than to one layout
+const MAIN_FILE_SETTINGS: &[&str] = &["layouts", "debug_dump_sequence", "pinned_passthrough_keys", "fast_bypass", "latch_modifiers"];
+
+// Removes a setting from the top level of the document, so that only the
+// mappings are left for the layout parser
+fn take_setting<T: DeserializeOwned + Default>(path: &str, root: &mut Value, name: &str) -> Result<T, String> {
+  match root {
+    Object(root_values) => match root_values.remove(name) {
+      None => Ok(T::default()),
+      Some(setting_v) => convert_json_error(
+        format!("parsing `{}` in {}", name, path).as_str(),
+        serde_json::from_value(setting_v)
+      )
+    },
+    _ => Ok(T::default())
+  }
+}
+
+// Loads the layouts listed under "layouts", each given either as a file
+// name relative to `path` or written out in place
+fn load_layouts(path: &Path, layouts_v: Value) -> Result<Vec<Layout>, String> {
+  let entries = match layouts_v {
+    Array(entries) => entries,
+    other => return Err(format!("Error in {}: `layouts` must be a list of layouts, found {}", path.display(), other))
+  };
+  
+  let base_dir = path.parent().unwrap_or(Path::new("."));
+  
+  let mut layouts = Vec::new();
+  for (i, entry) in entries.into_iter().enumerate() {
+    let (source, doc) = match entry {
+      Value::String(name) => {
+        let layout_path = base_dir.join(&name);
+        (layout_path.display().to_string(), load_json_with_includes(&layout_path, &mut Vec::new())?)
+      },
+      Object(values) => (format!("layout {} of {}", i + 1, path.display()), Object(values)),
+      other => return Err(format!("Error in {}: each of `layouts` must be a file name or a layout, found {}", path.display(), other))
+    };
+    
+    // Only the main file's settings apply
+    if let Object(values) = &doc {
+      for key in MAIN_FILE_SETTINGS {
+        if values.contains_key(*key) {
+          return Err(format!("Error in {}: `{}` is only supported in the main layout", source, key));
+        }
+      }
+    }
+    
+    let layout = crate::fancy_layout_interpreting::convert(
+      &crate::layout_parsing_formatting::parse_layout_from_json(&doc)
+        .map_err(|e| format!("Error in {}: {}", source, e))?
+    ).map_err(|e| format!("Error in {}: {}", source, e))?;
+    layouts.push(layout);
+  }
+  
+  Ok(layouts)
+}
+
+fn load_json_file(path: &Path) -> Result<Value, String> {
   let file_in =
     convert_io_error(
-      format!("reading {}", path).as_str(),
+      format!("reading {}", path.display()).as_str(),
       OpenOptions::new()
         .truncate(false).read(true).create(false).write(false)
         .open(path)
@@ -29,16 +114,169 @@ pub fn load_layout_from_file(path: &str) -> Result<Layout, String> {
       
   let buf_reader = std::io::BufReader::new(file_in);
   
-  crate::fancy_layout_interpreting::convert(
-    &crate::layout_parsing_formatting::parse_layout_from_json(
-      &convert_json_error(
-        format!("parsing {}", path).as_str(),
-        serde_json::from_reader(buf_reader)
-      )?
-    )?
+  convert_json_error(
+    format!("parsing {}", path.display()).as_str(),
+    serde_json::from_reader(buf_reader)
   )
 }
 
+// Loads a layout document, splicing in the mappings of any files listed
+// under "include" ahead of the document's own mappings. Include paths are
+// resolved relative to the including file. `including` holds the chain of
+// files currently being loaded so that cycles can be reported.
+fn load_json_with_includes(path: &Path, including: &mut Vec<PathBuf>) -> Result<Value, String> {
+  let canonical = convert_io_error(
+    format!("reading {}", path.display()).as_str(),
+    path.canonicalize()
+  )?;
+  
+  if including.contains(&canonical) {
+    return Err(format!("Error including {}: include cycle detected", path.display()));
+  }
+  
+  let root = load_json_file(path)?;
+  
+  let mut root_values = match root {
+    Object(root_values) => root_values,
+    other => return Ok(other)
+  };
+  
+  let groups = match root_values.remove("modifier_groups") {
+    None => serde_json::Map::new(),
+    Some(groups_v) => parse_modifier_groups(path, groups_v)?
+  };
+  if let Some(Array(own_mappings)) = root_values.get_mut("mappings") {
+    for m in own_mappings {
+      expand_modifier_groups(path, m, &groups)?;
+    }
+  }
+  
+  let include_v = match root_values.remove("include") {
+    None => return Ok(Object(root_values)),
+    Some(include_v) => include_v
+  };
+  
+  let include_names = match include_v {
+    Array(elems) => {
+      let mut names = Vec::new();
+      for elem in elems {
+        match elem {
+          Value::String(name) => names.push(name),
+          other => return Err(format!("Error in {}: `include` must be a list of file names, found {}", path.display(), other))
+        }
+      }
+      names
+    },
+    Value::String(name) => vec![name],
+    other => return Err(format!("Error in {}: `include` must be a list of file names, found {}", path.display(), other))
+  };
+  
+  let base_dir = path.parent().unwrap_or(Path::new("."));
+  
+  including.push(canonical);
+  
+  let mut mappings = Vec::new();
+  for name in include_names {
+    let include_path = base_dir.join(&name);
+    let included = load_json_with_includes(&include_path, including)?;
+    mappings.append(&mut mappings_of(&include_path, included)?);
+  }
+  
+  including.pop();
+  
+  if let Some(own_mappings) = root_values.remove("mappings") {
+    match own_mappings {
+      Array(mut own_mappings) => mappings.append(&mut own_mappings),
+      _ => return Err(format!("Error in {}: \"mappings\" must be an array", path.display()))
+    }
+  }
+  
+  root_values.insert("mappings".to_owned(), Array(mappings));
+  
+  Ok(Object(root_values))
+}
+
+fn mappings_of(path: &Path, doc: Value) -> Result<Vec<Value>, String> {
+  match doc {
+    Object(mut values) => {
+      let mappings = match values.remove("mappings") {
+        None => vec![],
+        Some(Array(mappings)) => mappings,
+        Some(_) => return Err(format!("Error in {}: \"mappings\" must be an array", path.display()))
+      };
+      
+      // Anything else in an included file would be silently lost, since
+      // only its mappings are spliced into the including file
+      if let Some(key) = values.keys().next() {
+        return Err(format!("Error in {}: `{}` is not supported in an included file", path.display(), key));
+      }
+      
+      Ok(mappings)
+    },
+    _ => Err(format!("Error in {}: Layout JSON must be an object", path.display()))
+  }
+}
+
+fn parse_modifier_groups(path: &Path, groups_v: Value) -> Result<serde_json::Map<String, Value>, String> {
+  let groups = match groups_v {
+    Object(groups) => groups,
+    other => return Err(format!("Error in {}: `modifier_groups` must be an object, found {}", path.display(), other))
+  };
+  
+  for (name, keys) in &groups {
+    match keys {
+      Array(keys) if keys.iter().all(|k| k.is_string()) => (),
+      other => return Err(format!("Error in {}: modifier group {} must be a list of keys, found {}", path.display(), name, other))
+    }
+  }
+  
+  Ok(groups)
+}
+
+// Replaces "$name" in a mapping's "from" and "to" with the keys of the
+// modifier group called `name`. Groups only apply to the file that
+// defines them.
+fn expand_modifier_groups(path: &Path, mapping: &mut Value, groups: &serde_json::Map<String, Value>) -> Result<(), String> {
+  let fields = match mapping {
+    Object(fields) => fields,
+    _ => return Ok(())
+  };
+  
+  for field in &["from", "to"] {
+    let keys = match fields.get(*field) {
+      Some(Array(keys)) => keys.clone(),
+      Some(Value::String(key)) => vec![Value::String(key.clone())],
+      _ => continue
+    };
+    
+    if !keys.iter().any(|k| group_name(k).is_some()) {
+      continue;
+    }
+    
+    let mut expanded = Vec::new();
+    for k in keys {
+      match group_name(&k) {
+        None => expanded.push(k),
+        Some(name) => match groups.get(name) {
+          Some(Array(group_keys)) => expanded.extend(group_keys.iter().cloned()),
+          _ => return Err(format!("Error in {}: no modifier group named {}", path.display(), name))
+        }
+      }
+    }
+    
+    fields.insert(field.to_string(), Array(expanded));
+  }
+  
+  Ok(())
+}
+
+fn group_name(key: &Value) -> Option<&str> {
+  match key {
+    Value::String(s) => s.strip_prefix('$'),
+    _ => None
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use crate::keys::Layout;
@@ -90,5 +328,253 @@ mod tests {
       }
     "#).unwrap();
   }
+  
+  // Removes the directory when dropped, so tests don't leave files behind
+  // even if they fail
+  struct TestDir {
+    path: std::path::PathBuf
+  }
+  
+  impl std::ops::Deref for TestDir {
+    type Target = std::path::Path;
+    
+    fn deref(&self) -> &std::path::Path {
+      &self.path
+    }
+  }
+  
+  impl Drop for TestDir {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_dir_all(&self.path);
+    }
+  }
+  
+  fn write_test_files(dir_name: &str, files: &[(&str, &str)]) -> TestDir {
+    let path = std::env::temp_dir().join(format!("totalmapper-{}-{}", dir_name, std::process::id()));
+    std::fs::create_dir_all(&path).unwrap();
+    for (name, text) in files {
+      std::fs::write(path.join(name), text).unwrap();
+    }
+    TestDir { path }
+  }
+  
+  #[test]
+  fn include_test_1() {
+    use crate::keys::{Mapping, KeyCode::*};
+    
+    let dir = write_test_files("include-1", &[
+      ("base.json", r#"{ "mappings": [ { "from": "CAPSLOCK", "to": [] } ] }"#),
+      ("nav.json", r#"{ "mappings": [ { "from": ["CAPSLOCK", "J"], "to": "LEFT" } ] }"#),
+      ("main.json", r#"
+        {
+          "include": ["base.json", "nav.json"],
+          "mappings": [
+            { "from": ["CAPSLOCK", "Q"], "to": "ESC" }
+          ]
+        }
+      "#),
+    ]);
+    
+    let layout = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap();
+    assert_eq!(layout.mappings, vec![
+      Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+      Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      Mapping { from: vec![CAPSLOCK, Q], to: vec![ESC], ..Default::default() },
+    ]);
+  }
+  
+  #[test]
+  fn include_cycle_test() {
+    let dir = write_test_files("include-cycle", &[
+      ("a.json", r#"{ "include": ["b.json"], "mappings": [] }"#),
+      ("b.json", r#"{ "include": ["a.json"], "mappings": [] }"#),
+    ]);
+    
+    let err = super::load_layout_from_file(dir.join("a.json").to_str().unwrap()).unwrap_err();
+    assert!(err.contains("include cycle"), "{}", err);
+  }
+  
+  #[test]
+  fn include_missing_test() {
+    let dir = write_test_files("include-missing", &[
+      ("a.json", r#"{ "include": ["missing.json"], "mappings": [] }"#),
+    ]);
+    
+    let err = super::load_layout_from_file(dir.join("a.json").to_str().unwrap()).unwrap_err();
+    assert!(err.contains("missing.json"), "{}", err);
+  }
+  
+  #[test]
+  fn modifier_groups_test() {
+    use crate::keys::{Mapping, KeyCode::*};
+    
+    let dir = write_test_files("modifier-groups", &[
+      ("main.json", r#"
+        {
+          "modifier_groups": { "hyper": ["LEFTCTRL", "LEFTSHIFT"] },
+          "mappings": [
+            { "from": ["$hyper", "H"], "to": "LEFT" },
+            { "from": "F1", "to": ["$hyper", "T"] }
+          ]
+        }
+      "#),
+    ]);
+    
+    let layout = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap();
+    assert_eq!(layout.mappings, vec![
+      Mapping { from: vec![LEFTCTRL, LEFTSHIFT, H], to: vec![LEFT], ..Default::default() },
+      Mapping { from: vec![F1], to: vec![LEFTCTRL, LEFTSHIFT, T], ..Default::default() },
+    ]);
+  }
+  
+  #[test]
+  fn modifier_groups_unknown_test() {
+    let dir = write_test_files("modifier-groups-unknown", &[
+      ("main.json", r#"{ "mappings": [ { "from": ["$hyper", "H"], "to": "LEFT" } ] }"#),
+    ]);
+    
+    let err = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap_err();
+    assert!(err.contains("hyper"), "{}", err);
+  }
+  
+  #[test]
+  fn debug_dump_sequence_test() {
+    use crate::keys::KeyCode::*;
+    
+    let dir = write_test_files("debug-dump", &[
+      ("main.json", r#"
+        {
+          "debug_dump_sequence": ["LEFTCTRL", "RIGHTCTRL", "F12"],
+          "mappings": [ { "from": "CAPSLOCK", "to": [] } ]
+        }
+      "#),
+    ]);
+    
+    let layout = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap();
+    assert_eq!(layout.debug_dump_sequence, Some(vec![LEFTCTRL, RIGHTCTRL, F12]));
+  }
+  
+  #[test]
+  fn settings_test() {
+    use crate::keys::KeyCode::*;
+    
+    let dir = write_test_files("settings", &[
+      ("main.json", r#"
+        {
+          "pinned_passthrough_keys": ["SPACE"],
+          "fast_bypass": ["W", "A", "S", "D"],
+          "latch_modifiers": ["LEFTSHIFT", "LEFTCTRL"],
+          "mappings": [ { "from": ["CAPSLOCK", "J"], "to": "SPACE" } ]
+        }
+      "#),
+    ]);
+    
+    let layout = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap();
+    assert_eq!(layout.pinned_passthrough_keys, vec![SPACE]);
+    assert_eq!(layout.fast_bypass, vec![W, A, S, D]);
+    assert_eq!(layout.latch_modifiers, vec![LEFTSHIFT, LEFTCTRL]);
+  }
+  
+  #[test]
+  fn include_unsupported_key_test() {
+    let dir = write_test_files("include-unsupported-key", &[
+      ("debug.json", r#"{ "debug_dump_sequence": ["F12"], "mappings": [] }"#),
+      ("main.json", r#"{ "include": ["debug.json"], "mappings": [] }"#),
+    ]);
+    
+    let err = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap_err();
+    assert!(err.contains("debug.json") && err.contains("debug_dump_sequence"), "{}", err);
+  }
+  
+  #[test]
+  fn layouts_test() {
+    use crate::keys::KeyCode::*;
+    use crate::keys::Event::*;
+    use crate::key_transforms::Mapper;
+    
+    let dir = write_test_files("layouts", &[
+      ("gaming.json", r#"
+        {
+          "mappings": [
+            { "from": "SCROLLLOCK", "to": [], "load_layout": 0 },
+            { "from": "J", "to": "L" }
+          ]
+        }
+      "#),
+      ("main.json", r#"
+        {
+          "layouts": [
+            "gaming.json",
+            { "mappings": [ { "from": "SCROLLLOCK", "to": [], "load_layout": 0 } ] }
+          ],
+          "mappings": [
+            { "from": "SCROLLLOCK", "to": [], "load_layout": 1 },
+            { "from": "J", "to": "K" }
+          ]
+        }
+      "#),
+    ]);
+    
+    let layout = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap();
+    assert_eq!(layout.layouts.len(), 2);
+    
+    let mut mapper = Mapper::for_layout(&layout);
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(K)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(K)]);
+    mapper.step(Pressed(SCROLLLOCK));
+    mapper.step(Released(SCROLLLOCK));
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(L)]);
+    assert_eq!(mapper.step(Released(J)).events, vec![Released(L)]);
+    mapper.step(Pressed(SCROLLLOCK));
+    mapper.step(Released(SCROLLLOCK));
+    assert_eq!(mapper.step(Pressed(J)).events, vec![Pressed(K)]);
+  }
+  
+  #[test]
+  fn nested_layouts_test() {
+    let dir = write_test_files("nested-layouts", &[
+      ("other.json", r#"{ "layouts": [], "mappings": [] }"#),
+      ("main.json", r#"{ "layouts": ["other.json"], "mappings": [] }"#),
+    ]);
+    
+    let err = super::load_layout_from_file(dir.join("main.json").to_str().unwrap()).unwrap_err();
+    assert!(err.contains("other.json") && err.contains("layouts"), "{}", err);
+  }
+  
+  // add_systemd_service saves the converted layout with serde, and the
+  // service then loads it back through the layout parser
+  #[test]
+  fn saved_layout_test() {
+    use crate::keys::Mapping;
+    use crate::keys::KeyCode::*;
+    use crate::keys::LayerAction;
+    
+    let mut layout = crate::fancy_layout_interpreting::convert(
+      &crate::layout_parsing_formatting::parse_layout_from_json(
+        &serde_json::from_str(crate::default_fancy_layouts::SUPER_DVORAK).unwrap()
+      ).unwrap()
+    ).unwrap();
+    layout.mappings.push(Mapping {
+      from: vec![F13], to: vec![LEFTCTRL, F1], release_override: Some(vec![LEFTCTRL, F2]), ..Default::default()
+    });
+    layout.mappings.push(Mapping {
+      from: vec![F14], to: vec![], layer: Some(LayerAction::Push(F14)), load_layout: Some(0), ..Default::default()
+    });
+    
+    let dir = write_test_files("saved", &[
+      ("totalmapper.json", &serde_json::to_string_pretty(&layout).unwrap()),
+    ]);
+    
+    let loaded = super::load_layout_from_file(dir.join("totalmapper.json").to_str().unwrap()).unwrap();
+    assert_eq!(loaded.mappings, layout.mappings);
+  }
+  
+  #[test]
+  fn test_files_are_removed() {
+    let dir = write_test_files("removed", &[("a.json", "{}")]);
+    let path = dir.to_path_buf();
+    assert!(path.join("a.json").exists());
+    drop(dir);
+    assert!(!path.exists());
+  }
 }
-
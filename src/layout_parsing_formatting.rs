@@ -3,7 +3,7 @@ use std::str::FromStr;
 use key_codes::KeyCode;
 use serde_json::{Value, Map};
 use Value::{Object, Array};
-use crate::{fancy_keys::{Layout, Mapping, SingleMapping, AliasMapping, RowMapping, Modifier, SingleFromKeys, RowFromKeys, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, Row, AliasToKeys, AliasFromKeys, RepeatOnlySingleMapping}, key_codes};
+use crate::{fancy_keys::{Layout, Mapping, SingleMapping, AliasMapping, RowMapping, Modifier, SingleFromKeys, RowFromKeys, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, Row, AliasToKeys, AliasFromKeys, RepeatOnlySingleMapping, MappingOptions, LayerAction}, key_codes};
 use serde_json::Value as j;
 use serde_json::json;
 use lazy_static::lazy_static;
@@ -109,6 +109,12 @@ fn parse_mapping_from_json(mapping_v: &Value) -> Result<Mapping, String> {
   match mapping_v {
     Object(mapping_values) => {
       if has_at_least_keys(mapping_values, &vec!["from", "to"]) {
+        for key in mapping_values.keys() {
+          if !["from", "to", "repeat", "absorbing"].contains(&key.as_str()) && !MAPPING_OPTION_NAMES.contains(&key.as_str()) {
+            return Err(format!("Unknown mapping key `{}`", key));
+          }
+        }
+        
         let from = parse_from(mapping_values.get("from").unwrap())?;
         match from {
           FromKeys::Single(from) => {
@@ -123,13 +129,18 @@ fn parse_mapping_from_json(mapping_v: &Value) -> Result<Mapping, String> {
                   }
                 }
                 
+                let options = parse_mapping_options(mapping_values)?;
+                
                 Ok(Mapping::Single(SingleMapping {
-                  from, to, repeat, absorbing
+                  from, to, repeat, absorbing, options
                 }))
               },
               SingleOrAliasToKeys::Alias(to) => {
                 if mapping_values.contains_key("repeat") { Err("`repeat` not allowed for alias mappings")?; }
                 if mapping_values.contains_key("absorbing") { Err("`absorbing` not allowed for alias mappings")?; }
+                for name in MAPPING_OPTION_NAMES {
+                  if mapping_values.contains_key(*name) { Err(format!("`{}` not allowed for alias mappings", name))?; }
+                }
                 Ok(Mapping::Alias(AliasMapping { from: single_to_alias_from(&from)?, to }))
               }
             }
@@ -161,8 +172,10 @@ fn parse_mapping_from_json(mapping_v: &Value) -> Result<Mapping, String> {
               }
             }
 
+            let options = parse_mapping_options(mapping_values)?;
+
             Ok(Mapping::Row(RowMapping {
-              from, to, repeat, absorbing
+              from, to, repeat, absorbing, options
             }))
           }
         }
@@ -536,7 +549,7 @@ fn parse_key_code_j(v: &Value) -> Result<KeyCode, String> {
   }
 }
 
-fn parse_key_code(text: &str) -> Result<KeyCode, String> {
+pub fn parse_key_code(text: &str) -> Result<KeyCode, String> {
   if text.starts_with("@") {
     Err(format!("A real key was expected, but alias modifier {} was found", text))
   }
@@ -552,7 +565,9 @@ fn parse_key_code(text: &str) -> Result<KeyCode, String> {
       "7" => Ok(KeyCode::K7),
       "8" => Ok(KeyCode::K8),
       "9" => Ok(KeyCode::K9),
-      _ => KeyCode::from_str(&text).map_err(|_| format!("Unknown key code: {}", text))
+      _ => KeyCode::from_str(text).ok()
+        .or_else(|| key_codes::from_keysym_name(text))
+        .ok_or_else(|| format!("Unknown key code: {}", text))
     }
   }
 }
@@ -716,6 +731,86 @@ fn parse_absorbing(v: &Option<&Value>) -> Result<Vec<Modifier>, String> {
   }
 }
 
+// The keys of a single or row mapping that end up in `MappingOptions`
+const MAPPING_OPTION_NAMES: &[&str] = &["ignore_extra_modifiers", "layer", "clear_first", "ordered", "silent_release", "sticky_layer", "clear_on_next_key", "progressive_outputs", "release_override", "load_layout", "tags"];
+
+fn parse_mapping_options(values: &Map<String, Value>) -> Result<MappingOptions, String> {
+  Ok(MappingOptions {
+    ignore_extra_modifiers: parse_bool_option("ignore_extra_modifiers", &values.get("ignore_extra_modifiers"))?,
+    layer: parse_layer_action(&values.get("layer"))?,
+    clear_first: parse_bool_option("clear_first", &values.get("clear_first"))?,
+    ordered: parse_bool_option("ordered", &values.get("ordered"))?,
+    silent_release: parse_bool_option("silent_release", &values.get("silent_release"))?,
+    sticky_layer: parse_bool_option("sticky_layer", &values.get("sticky_layer"))?,
+    clear_on_next_key: parse_bool_option("clear_on_next_key", &values.get("clear_on_next_key"))?,
+    progressive_outputs: parse_progressive_outputs(&values.get("progressive_outputs"))?,
+    release_override: match values.get("release_override") {
+      None | Some(j::Null) => None,
+      Some(v) => Some(parse_key_list("release_override", v)?)
+    },
+    load_layout: parse_load_layout(&values.get("load_layout"))?,
+    tags: parse_tags(&values.get("tags"))?
+  })
+}
+
+fn parse_load_layout(v: &Option<&Value>) -> Result<Option<usize>, String> {
+  match v {
+    None | Some(j::Null) => Ok(None),
+    Some(j::Number(n)) if n.as_u64().is_some() => Ok(Some(n.as_u64().unwrap() as usize)),
+    Some(v) => Err(format!("`load_layout` must be a layout index, found {}", v))
+  }
+}
+
+fn parse_tags(v: &Option<&Value>) -> Result<Vec<String>, String> {
+  match v {
+    None => Ok(vec![]),
+    Some(Array(elems)) => elems.iter().map(|elem| match elem {
+      j::String(tag) => Ok(tag.clone()),
+      _ => Err(format!("Each tag must be a string, found {}", elem))
+    }).collect(),
+    Some(v) => Err(format!("`tags` must be a list of strings, found {}", v))
+  }
+}
+
+fn parse_progressive_outputs(v: &Option<&Value>) -> Result<Vec<Vec<KeyCode>>, String> {
+  match v {
+    None => Ok(vec![]),
+    Some(Array(elems)) => elems.iter().map(|elem| parse_key_list("progressive_outputs", elem)).collect(),
+    Some(v) => Err(format!("`progressive_outputs` must be a list of key lists, found {}", v))
+  }
+}
+
+// A list of keys, or a single key on its own
+fn parse_key_list(name: &str, v: &Value) -> Result<Vec<KeyCode>, String> {
+  match v {
+    Array(elems) => elems.iter().map(parse_key_code_j).collect(),
+    j::String(text) => Ok(vec![parse_key_code(text)?]),
+    _ => Err(format!("`{}` must be a list of keys, found {}", name, v))
+  }
+}
+
+fn parse_layer_action(v: &Option<&Value>) -> Result<Option<LayerAction>, String> {
+  match v {
+    None | Some(j::Null) => Ok(None),
+    Some(j::String(text)) if text == "pop" => Ok(Some(LayerAction::Pop)),
+    Some(Object(attrs)) if has_exactly_keys(attrs, &vec!["push"]) => {
+      Ok(Some(LayerAction::Push(parse_key_code_j(attrs.get("push").unwrap())?)))
+    },
+    Some(Object(attrs)) if has_exactly_keys(attrs, &vec!["one_shot"]) => {
+      Ok(Some(LayerAction::OneShot(parse_key_code_j(attrs.get("one_shot").unwrap())?)))
+    },
+    Some(v) => Err(format!("`layer` must be \"pop\", {{\"push\": <key>}} or {{\"one_shot\": <key>}}, found {}", v))
+  }
+}
+
+fn parse_bool_option(name: &str, v: &Option<&Value>) -> Result<bool, String> {
+  match v {
+    None => Ok(false),
+    Some(j::Bool(b)) => Ok(*b),
+    Some(v) => Err(format!("`{}` must be true or false, found {}", name, v))
+  }
+}
+
 fn keys_string(values: &Map<String, Value>) -> String {
   let v1: Vec<&str> = values.keys().map(|s|s.as_str()).collect();
   v1.join(", ")
@@ -768,6 +863,7 @@ fn format_single_mapping(mapping: &SingleMapping) -> Value {
   if let Some(absorbing) = format_absorbing(&mapping.absorbing) {
     keys.insert("absorbing".to_owned(), absorbing);
   }
+  format_mapping_options(&mapping.options, &mut keys);
   
   j::Object(keys)
 }
@@ -803,10 +899,60 @@ fn format_row_mapping(mapping: &RowMapping) -> Value {
   if let Some(absorbing) = format_absorbing(&mapping.absorbing) {
     keys.insert("absorbing".to_owned(), absorbing);
   }
+  format_mapping_options(&mapping.options, &mut keys);
   
   j::Object(keys)
 }
 
+fn format_mapping_options(options: &MappingOptions, keys: &mut Map<String, Value>) {
+  if options.ignore_extra_modifiers {
+    keys.insert("ignore_extra_modifiers".to_owned(), j::Bool(true));
+  }
+  if let Some(layer) = &options.layer {
+    keys.insert("layer".to_owned(), format_layer_action(layer));
+  }
+  if options.clear_first {
+    keys.insert("clear_first".to_owned(), j::Bool(true));
+  }
+  if options.ordered {
+    keys.insert("ordered".to_owned(), j::Bool(true));
+  }
+  if options.silent_release {
+    keys.insert("silent_release".to_owned(), j::Bool(true));
+  }
+  if options.sticky_layer {
+    keys.insert("sticky_layer".to_owned(), j::Bool(true));
+  }
+  if options.clear_on_next_key {
+    keys.insert("clear_on_next_key".to_owned(), j::Bool(true));
+  }
+  if !options.progressive_outputs.is_empty() {
+    let outputs: Vec<Value> = options.progressive_outputs.iter().map(|keys| format_key_list(keys)).collect();
+    keys.insert("progressive_outputs".to_owned(), j::Array(outputs));
+  }
+  if let Some(release_override) = &options.release_override {
+    keys.insert("release_override".to_owned(), format_key_list(release_override));
+  }
+  if let Some(load_layout) = options.load_layout {
+    keys.insert("load_layout".to_owned(), json!(load_layout));
+  }
+  if !options.tags.is_empty() {
+    keys.insert("tags".to_owned(), json!(options.tags));
+  }
+}
+
+fn format_key_list(keys: &[KeyCode]) -> Value {
+  j::Array(keys.iter().map(format_key_code).collect())
+}
+
+fn format_layer_action(layer: &LayerAction) -> Value {
+  match layer {
+    LayerAction::Push(k) => json!({ "push": format_key_code(k) }),
+    LayerAction::Pop => json!("pop"),
+    LayerAction::OneShot(k) => json!({ "one_shot": format_key_code(k) })
+  }
+}
+
 fn format_single_from(from: &SingleFromKeys) -> Value {
   let mut elems = Vec::new();
   
@@ -1021,7 +1167,7 @@ fn format_absorbing(absorbing: &Vec<Modifier>) -> Option<Value> {
 #[cfg(test)]
 mod tests {
   use std::str::FromStr;
-  use crate::fancy_keys::{Layout, Mapping, SingleMapping, RowMapping, SingleFromKeys, RowFromKeys, Modifier, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, AliasMapping, AliasFromKeys, AliasToKeys};
+  use crate::fancy_keys::{Layout, Mapping, SingleMapping, RowMapping, SingleFromKeys, RowFromKeys, Modifier, SingleToKeys, RowToKeys, SingleTerminalToKey, SingleRepeat, RowRepeat, AliasMapping, AliasFromKeys, AliasToKeys, MappingOptions, LayerAction};
   use super::{parse_layout_from_json, format_layout_as_json};
   use crate::key_codes::KeyCode::*;
 
@@ -1062,9 +1208,9 @@ mod tests {
         Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![CAPSLOCK] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() } }),
         Mapping::Alias(AliasMapping { from: AliasFromKeys { keys: vec![RIGHTALT] }, to: AliasToKeys { initial: vec![], terminal: "@symbol".to_owned() } }),
       
-        Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyQ }, to: RowToKeys { initial: vec![], terminal: " {}% \\*][|".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![] }),
-        Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyA }, to: RowToKeys { initial: vec![], terminal: "   = &)(/_$".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![] }),
-        Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyZ }, to: RowToKeys { initial: vec![], terminal: "\"    !+#".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![] }),
+        Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyQ }, to: RowToKeys { initial: vec![], terminal: " {}% \\*][|".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![], options: MappingOptions::default() }),
+        Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyA }, to: RowToKeys { initial: vec![], terminal: "   = &)(/_$".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![], options: MappingOptions::default() }),
+        Mapping::Row(RowMapping { from: RowFromKeys { modifiers: vec![Modifier::Alias("@symbol".to_owned())], row: USQuertyZ }, to: RowToKeys { initial: vec![], terminal: "\"    !+#".to_owned() }, repeat: RowRepeat::Normal, absorbing: vec![], options: MappingOptions::default() }),
       ]
     });
   }
@@ -1080,7 +1226,7 @@ mod tests {
     let parsed = parse_layout_from_json(&json).unwrap();
     assert_eq!(parsed, Layout {
       mappings: vec![
-        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: COMMA }, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(W) }, repeat: SingleRepeat::Special { keys: SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(F24) }, delay_ms: 180, interval_ms: 30 }, absorbing: vec![] })
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: COMMA }, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(W) }, repeat: SingleRepeat::Special { keys: SingleToKeys { initial: vec![Modifier::Key(LEFTCTRL)], terminal: SingleTerminalToKey::Physical(F24) }, delay_ms: 180, interval_ms: 30 }, absorbing: vec![], options: MappingOptions::default() })
       ]
     });
   }
@@ -1140,11 +1286,175 @@ mod tests {
     let parsed = parse_layout_from_json(&json).unwrap();
     assert_eq!(parsed, Layout {
       mappings: vec![
-        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: K4}, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(K3) }, repeat: SingleRepeat::Normal, absorbing: vec![] })
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![], key: K4}, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(K3) }, repeat: SingleRepeat::Normal, absorbing: vec![], options: MappingOptions::default() })
       ]
     });
   }
 
+  #[test]
+  fn test_keysym_names() {
+    let text = r#"{
+  "mappings": [
+    {"from":["Control_L", "bracketleft"], "to":"Escape"}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(LEFTCTRL)], key: LEFTBRACE}, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(ESC) }, repeat: SingleRepeat::Normal, absorbing: vec![], options: MappingOptions::default() })
+      ]
+    });
+  }
+
+  #[test]
+  fn test_mapping_options() {
+    let text = r#"{
+  "mappings": [
+    {"from":["CAPSLOCK","J"], "to":"LEFT", "ignore_extra_modifiers":true}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(parsed, Layout {
+      mappings: vec![
+        Mapping::Single(SingleMapping { from: SingleFromKeys { modifiers: vec![Modifier::Key(CAPSLOCK)], key: J}, to: SingleToKeys { initial: vec![], terminal: SingleTerminalToKey::Physical(LEFT) }, repeat: SingleRepeat::Normal, absorbing: vec![], options: MappingOptions { ignore_extra_modifiers: true, ..Default::default() } })
+      ]
+    });
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let converted = crate::fancy_layout_interpreting::convert(&parsed).unwrap();
+    assert!(converted.mappings[0].ignore_extra_modifiers);
+  }
+
+  #[test]
+  fn test_layer_actions() {
+    let text = r#"{
+  "mappings": [
+    {"from":"F1", "to":[], "layer":{"push":"CAPSLOCK"}},
+    {"from":"F2", "to":[], "layer":"pop"},
+    {"from":"F3", "to":[], "layer":{"one_shot":"TAB"}}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    let layers: Vec<Option<LayerAction>> = parsed.mappings.iter().map(|m| match m {
+      Mapping::Single(single) => single.options.layer.clone(),
+      _ => None
+    }).collect();
+    assert_eq!(layers, vec![Some(LayerAction::Push(CAPSLOCK)), Some(LayerAction::Pop), Some(LayerAction::OneShot(TAB))]);
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let parse = |text: &str| parse_layout_from_json(&serde_json::Value::from_str(text).unwrap());
+    assert!(parse(r#"{"mappings": [{"from":"F1", "to":[], "layer":"push"}]}"#).is_err());
+    assert!(parse(r#"{"mappings": [{"from":"F1", "to":[], "layer":{"push":"@shift"}}]}"#).is_err());
+  }
+
+  #[test]
+  fn test_progressive_outputs() {
+    let text = r#"{
+  "mappings": [
+    {"from":["CAPSLOCK","J"], "to":[], "progressive_outputs":[["HOME"],["LEFTCTRL","LEFT"]]}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let converted = crate::fancy_layout_interpreting::convert(&parsed).unwrap();
+    assert_eq!(converted.mappings[0].progressive_outputs, vec![vec![HOME], vec![LEFTCTRL, LEFT]]);
+    
+    let parse = |text: &str| parse_layout_from_json(&serde_json::Value::from_str(text).unwrap());
+    assert!(parse(r#"{"mappings": [{"from":"J", "to":[], "progressive_outputs":"HOME"}]}"#).is_err());
+    assert!(parse(r#"{"mappings": [{"from":"J", "to":[], "progressive_outputs":[["@shift"]]}]}"#).is_err());
+  }
+
+  #[test]
+  fn test_release_override() {
+    let text = r#"{
+  "mappings": [
+    {"from":"F13", "to":["LEFTCTRL","F1"], "release_override":["LEFTCTRL","F2"]}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let converted = crate::fancy_layout_interpreting::convert(&parsed).unwrap();
+    assert_eq!(converted.mappings[0].release_override, Some(vec![LEFTCTRL, F2]));
+  }
+
+  #[test]
+  fn test_load_layout() {
+    let text = r#"{
+  "mappings": [
+    {"from":"SCROLLLOCK", "to":[], "load_layout":1}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let converted = crate::fancy_layout_interpreting::convert(&parsed).unwrap();
+    assert_eq!(converted.mappings[0].load_layout, Some(1));
+    
+    let parse = |text: &str| parse_layout_from_json(&serde_json::Value::from_str(text).unwrap());
+    assert!(parse(r#"{"mappings": [{"from":"SCROLLLOCK", "to":[], "load_layout":-1}]}"#).is_err());
+    assert!(parse(r#"{"mappings": [{"from":"SCROLLLOCK", "to":[], "load_layout":"other"}]}"#).is_err());
+  }
+
+  #[test]
+  fn test_tags() {
+    let text = r#"{
+  "mappings": [
+    {"from":["CAPSLOCK","J"], "to":"LEFT", "tags":["navigation"]},
+    {"from":["CAPSLOCK","K"], "to":"DOWN"}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let converted = crate::fancy_layout_interpreting::convert(&parsed).unwrap();
+    assert_eq!(converted.mappings[0].tags, vec!["navigation".to_string()]);
+    assert!(converted.mappings[1].tags.is_empty());
+    
+    let parse = |text: &str| parse_layout_from_json(&serde_json::Value::from_str(text).unwrap());
+    assert!(parse(r#"{"mappings": [{"from":"J", "to":"LEFT", "tags":"navigation"}]}"#).is_err());
+  }
+
+  #[test]
+  fn test_bool_mapping_options() {
+    let text = r#"{
+  "mappings": [
+    {"from":"F1", "to":["LEFTCTRL","C"], "clear_first":true},
+    {"from":["LEFTCTRL","LEFTALT","K"], "to":"F13", "ordered":true},
+    {"from":"F5", "to":["LEFTCTRL","S"], "silent_release":true},
+    {"from":"CAPSLOCK", "to":[], "sticky_layer":true},
+    {"from":["CAPSLOCK","S"], "to":"LEFTSHIFT", "clear_on_next_key":true}
+  ]
+}"#;
+    let json = serde_json::Value::from_str(text).unwrap();
+    let parsed = parse_layout_from_json(&json).unwrap();
+    assert_eq!(json.to_string(), format_layout_as_json(&parsed).to_string());
+    
+    let converted = crate::fancy_layout_interpreting::convert(&parsed).unwrap();
+    assert!(converted.mappings[0].clear_first);
+    assert!(converted.mappings[1].ordered);
+    assert!(converted.mappings[2].silent_release);
+    assert!(converted.mappings[3].sticky_layer);
+    assert!(converted.mappings[4].clear_on_next_key);
+  }
+
+  #[test]
+  fn test_bad_mapping_options() {
+    let parse = |text: &str| parse_layout_from_json(&serde_json::Value::from_str(text).unwrap());
+    
+    assert!(parse(r#"{"mappings": [{"from":"A", "to":"B", "ignore_extra_modifiers":1}]}"#).is_err());
+    assert!(parse(r#"{"mappings": [{"from":"A", "to":"B", "while_held":["LEFTCTRL"]}]}"#).is_err());
+    assert!(parse(r#"{"mappings": [{"from":"LEFTSHIFT", "to":"@shift", "ignore_extra_modifiers":true}]}"#).is_err());
+  }
+
   #[test]
   fn test_numbers_2() {
     let text = r#"{
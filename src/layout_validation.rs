@@ -0,0 +1,295 @@
+// vim: shiftwidth=2
+
+use crate::keys::{Layout, Mapping, KeyCode, LayerAction, Repeat};
+use crate::key_transforms::{is_action_key, can_fire, select_mapping};
+use std::fmt::Display;
+
+// Problems (or likely mistakes) found in a layout. None of these stop the
+// layout from being used; they are reported so the author can check them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutError {
+  // A mapping to `[]` that no other mapping extends. This just disables
+  // the key(s); it does not act as a layer.
+  EmptyMappingIsNotLayer { from: Vec<KeyCode> },
+  // A key used to hold a layer (a non-final key of some trigger) that has
+  // no mapping of its own, so pressing it also types it
+  LayerKeyNotSwallowed { key: KeyCode },
+  // A layer key swallowed with `[]` that also disables repeat, which does
+  // nothing since there is no output to repeat
+  NoRepeatOnLayerTrigger { key: KeyCode },
+}
+
+impl Display for LayoutError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    use LayoutError::*;
+    
+    match self {
+      EmptyMappingIsNotLayer { from } => f.write_fmt(format_args!(
+        "{} is mapped to [] but no mapping uses it as a layer, so it is simply disabled",
+        format_keys(from)
+      )),
+      LayerKeyNotSwallowed { key } => f.write_fmt(format_args!(
+        "{} is used as a layer key but has no mapping of its own, so it will also be typed; add {{ \"from\": \"{}\", \"to\": [] }} to swallow it",
+        key, key
+      )),
+      NoRepeatOnLayerTrigger { key } => f.write_fmt(format_args!(
+        "{} is a layer key mapped to [], so disabling repeat on it has no effect",
+        key
+      )),
+    }
+  }
+}
+
+fn format_keys(keys: &[KeyCode]) -> String {
+  let names: Vec<String> = keys.iter().map(|k| format!("{}", k)).collect();
+  format!("[{}]", names.join(", "))
+}
+
+pub fn validate_layout(layout: &Layout) -> Vec<LayoutError> {
+  let mut res = Vec::new();
+
+  for m in &layout.mappings {
+    if m.to.is_empty() && m.progressive_outputs.is_empty() && m.layer.is_none() && m.load_layout.is_none() && !is_layer(layout, m) {
+      res.push(LayoutError::EmptyMappingIsNotLayer { from: m.from.clone() });
+    }
+  }
+
+  let mut unswallowed: Vec<KeyCode> = Vec::new();
+  for m in &layout.mappings {
+    for k in m.from.iter().take(m.from.len().saturating_sub(1)) {
+      if is_action_key(k) && !unswallowed.contains(k) && !is_swallowed(layout, k) {
+        unswallowed.push(*k);
+      }
+    }
+  }
+  for key in unswallowed {
+    res.push(LayoutError::LayerKeyNotSwallowed { key });
+  }
+  
+  for m in &layout.mappings {
+    if m.from.len() == 1 && m.to.is_empty() && m.repeat == Repeat::Disabled && is_layer(layout, m) {
+      res.push(LayoutError::NoRepeatOnLayerTrigger { key: m.from[0] });
+    }
+  }
+
+  res
+}
+
+// Whether pressing `k` on its own is handled by the layout, either by a
+// mapping for it or because it is only ever entered as a latched layer
+fn is_swallowed(layout: &Layout, k: &KeyCode) -> bool {
+  layout.mappings.iter().any(|m| {
+    m.from == [*k]
+      || m.layer == Some(LayerAction::Push(*k))
+      || m.layer == Some(LayerAction::OneShot(*k))
+  })
+}
+
+// Whether some other mapping is only reachable while `m`'s trigger is held
+pub fn is_layer(layout: &Layout, m: &Mapping) -> bool {
+  layout.mappings.iter().any(|other| {
+    other.from.len() > m.from.len()
+      && m.from.iter().all(|k| other.from[..other.from.len()-1].contains(k))
+  })
+}
+
+// Two mappings that can both fire on the same key press, and the one
+// that fires when they do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+  pub winner: Vec<KeyCode>,
+  pub loser: Vec<KeyCode>
+}
+
+impl Display for Conflict {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_fmt(format_args!(
+      "{} overrides {} when both could fire",
+      format_keys(&self.winner), format_keys(&self.loser)
+    ))
+  }
+}
+
+// Every pair of mappings with the same final key that some set of held
+// keys would let both fire, in layout order of the earlier mapping of
+// each pair. The winner is worked out as the mapper would, with
+// `select_mapping`.
+pub fn conflict_matrix(layout: &Layout) -> Vec<Conflict> {
+  let mut res = Vec::new();
+  
+  let mappings = &layout.mappings;
+  for i in 0 .. mappings.len() {
+    for j in i+1 .. mappings.len() {
+      let (a, b) = (&mappings[i], &mappings[j]);
+      let new_key = a.from[a.from.len() - 1];
+      if b.from[b.from.len() - 1] != new_key {
+        continue;
+      }
+      
+      // Hold the other keys of both triggers, trying both orders in case
+      // either mapping is `ordered`
+      for (first, second) in [(a, b), (b, a)] {
+        let mut pressed: Vec<KeyCode> = first.from[.. first.from.len() - 1].to_vec();
+        for k in &second.from[.. second.from.len() - 1] {
+          if !pressed.contains(k) {
+            pressed.push(*k);
+          }
+        }
+        
+        if can_fire(a, &pressed, new_key) && can_fire(b, &pressed, new_key) {
+          let pair = [a.clone(), b.clone()];
+          let winner = select_mapping(&pair, &pressed, new_key).unwrap();
+          res.push(Conflict {
+            winner: pair[winner].from.clone(),
+            loser: pair[1 - winner].from.clone()
+          });
+          break;
+        }
+      }
+    }
+  }
+  
+  res
+}
+
+// Keys from `universe` that the layout leaves alone: they are not part of
+// any mapping's trigger and no mapping pushes them as a layer. Pass
+// `get_all_keyboard_key_codes()` to check every key.
+pub fn unused_keys(layout: &Layout, universe: &[KeyCode]) -> Vec<KeyCode> {
+  universe.iter().filter(|k| {
+    !layout.mappings.iter().any(|m| {
+      m.from.contains(k) || m.layer == Some(LayerAction::Push(**k))
+    })
+  }).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use KeyCode::*;
+
+  #[test]
+  fn test_empty_mapping_as_layer() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(validate_layout(&layout), vec![]);
+  }
+
+  #[test]
+  fn test_empty_mapping_as_disable() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![INSERT], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(validate_layout(&layout), vec![
+      LayoutError::EmptyMappingIsNotLayer { from: vec![INSERT] }
+    ]);
+  }
+
+  #[test]
+  fn test_progressive_mapping_is_not_empty() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping {
+          from: vec![CAPSLOCK, J],
+          to: vec![],
+          progressive_outputs: vec![vec![HOME], vec![LEFTCTRL, HOME]],
+          ..Default::default()
+        },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(validate_layout(&layout), vec![]);
+  }
+
+  #[test]
+  fn test_unused_keys() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![F1], to: vec![], layer: Some(LayerAction::Push(TAB)), ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(unused_keys(&layout, &[CAPSLOCK, TAB, J, K, F1, LEFT]), vec![K, LEFT]);
+  }
+
+  #[test]
+  fn test_layer_key_not_swallowed() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![LEFTCTRL, J], to: vec![LEFT], ..Default::default() },
+        Mapping { from: vec![TAB], to: vec![], ..Default::default() },
+        Mapping { from: vec![TAB, M], to: vec![PAGEDOWN], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(validate_layout(&layout), vec![
+      LayoutError::LayerKeyNotSwallowed { key: CAPSLOCK }
+    ]);
+  }
+
+  #[test]
+  fn test_no_repeat_on_layer_trigger() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], repeat: Repeat::Disabled, ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], repeat: Repeat::Disabled, ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    assert_eq!(validate_layout(&layout), vec![
+      LayoutError::NoRepeatOnLayerTrigger { key: CAPSLOCK }
+    ]);
+  }
+
+  #[test]
+  fn test_conflict_matrix() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![TAB], to: vec![], ..Default::default() },
+        Mapping { from: vec![F], to: vec![U], ..Default::default() },
+        Mapping { from: vec![N], to: vec![B], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, M], to: vec![LEFTSHIFT, EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, F], to: vec![EQUAL], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, N], to: vec![LEFTSHIFT, K1], ..Default::default() },
+        Mapping { from: vec![TAB, M], to: vec![PAGEDOWN], ..Default::default() },
+        Mapping { from: vec![TAB, N], to: vec![LEFTCTRL, LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let conflict = |winner: Vec<KeyCode>, loser: Vec<KeyCode>| Conflict { winner, loser };
+    assert_eq!(conflict_matrix(&layout), vec![
+      conflict(vec![CAPSLOCK, F], vec![F]),
+      conflict(vec![CAPSLOCK, N], vec![N]),
+      conflict(vec![TAB, N], vec![N]),
+      conflict(vec![TAB, M], vec![CAPSLOCK, M]),
+      conflict(vec![TAB, N], vec![CAPSLOCK, N]),
+    ]);
+  }
+
+  #[test]
+  fn test_default_layouts_are_valid() {
+    use crate::default_fancy_layouts::DEFAULT_LAYOUTS;
+    use crate::layout_parsing_formatting::parse_layout_from_json;
+    use crate::fancy_layout_interpreting::convert;
+
+    for (name, json) in DEFAULT_LAYOUTS.iter() {
+      let layout = convert(&parse_layout_from_json(&serde_json::from_str(json).unwrap()).unwrap()).unwrap();
+      assert_eq!(validate_layout(&layout), vec![], "{}", name);
+    }
+  }
+}
@@ -17,6 +17,9 @@ mod remapping_loop;
 mod keyboard_listing;
 mod udev_utils;
 mod layout_loading;
+mod layout_validation;
+mod session_audit;
+mod cheatsheet;
 mod version;
 mod monitor;
 mod monitor_raw;
@@ -124,6 +127,125 @@ fn main() {
           .help("The name of the builtin layout to print. Use `totalmapper list_default_layouts` to see the list of builtin layouts.")
         )
       )
+      .subcommand(App::new("print_cheatsheet")
+        .about("Print a Markdown table of what each key does in a layout, with one table per layer")
+        .arg(Arg::new("default_layout")
+          .long("default-layout")
+          .takes_value(true)
+          .value_name("NAME")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Use the builtin layout named NAME. To list the builtin layouts, use `totalmapper list_default_layouts`.")
+        )
+        .arg(Arg::new("layout_file")
+          .long("layout-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Load a layout from json file FILE.")
+        )
+        .arg(Arg::new("tag")
+          .long("tag")
+          .takes_value(true)
+          .value_name("TAG")
+          .help("Only list mappings that have TAG in their `tags`.")
+        )
+      )
+      .subcommand(App::new("check_layout")
+        .about("Print likely mistakes in a layout without remapping anything")
+        .arg(Arg::new("default_layout")
+          .long("default-layout")
+          .takes_value(true)
+          .value_name("NAME")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Use the builtin layout named NAME. To list the builtin layouts, use `totalmapper list_default_layouts`.")
+        )
+        .arg(Arg::new("layout_file")
+          .long("layout-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Load a layout from json file FILE.")
+        )
+        .arg(Arg::new("conflicts")
+          .long("conflicts")
+          .help("Also list every pair of mappings that can fire on the same key press, and which one wins.")
+        )
+        .arg(Arg::new("unused_keys")
+          .long("unused-keys")
+          .help("Also list the keyboard keys that no mapping uses, which are free for new mappings.")
+        )
+      )
+      .subcommand(App::new("list_reachable_outputs")
+        .about("List what each key would type next while some keys are held")
+        .arg(Arg::new("default_layout")
+          .long("default-layout")
+          .takes_value(true)
+          .value_name("NAME")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Use the builtin layout named NAME. To list the builtin layouts, use `totalmapper list_default_layouts`.")
+        )
+        .arg(Arg::new("layout_file")
+          .long("layout-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Load a layout from json file FILE.")
+        )
+        .arg(Arg::new("held")
+          .long("held")
+          .takes_value(true)
+          .value_name("KEY")
+          .multiple_occurrences(true)
+          .number_of_values(1)
+          .help("A key that is held down, named as in a layout file, e.g. `--held CAPSLOCK`. Repeat this option for several held keys.")
+        )
+      )
+      .subcommand(App::new("type_text")
+        .about("Print the key events a layout sends when TEXT is typed on a US keyboard")
+        .arg(Arg::new("default_layout")
+          .long("default-layout")
+          .takes_value(true)
+          .value_name("NAME")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Use the builtin layout named NAME. To list the builtin layouts, use `totalmapper list_default_layouts`.")
+        )
+        .arg(Arg::new("layout_file")
+          .long("layout-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Load a layout from json file FILE.")
+        )
+        .arg(Arg::new("TEXT")
+          .required(true)
+          .index(1)
+          .help("The text to type. Characters with no key on a US keyboard are skipped.")
+        )
+      )
+      .subcommand(App::new("audit_session")
+        .about("Replay a session recorded with `totalmapper monitor` through a layout and report keys left stuck down")
+        .arg(Arg::new("default_layout")
+          .long("default-layout")
+          .takes_value(true)
+          .value_name("NAME")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Use the builtin layout named NAME. To list the builtin layouts, use `totalmapper list_default_layouts`.")
+        )
+        .arg(Arg::new("layout_file")
+          .long("layout-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .help_heading(Some("LAYOUT SELECTION"))
+          .help("Load a layout from json file FILE.")
+        )
+        .arg(Arg::new("session_file")
+          .long("session-file")
+          .takes_value(true)
+          .value_name("FILE")
+          .required(true)
+          .help("A file holding the output of `totalmapper monitor` while the problem happened.")
+        )
+      )
       .subcommand(App::new("monitor")
         .about("Print events from a keyboard device (without consuming them)")
         .arg(Arg::new("dev_file")
@@ -195,6 +317,9 @@ fn main() {
         std::process::exit(1);
       },
       Ok(layout) => {
+        for warning in layout_validation::validate_layout(&layout) {
+          println!("Warning: {}", warning);
+        }
         match (m.occurrences_of("all_keyboards") > 0, m.values_of("dev_file"), m.occurrences_of("auto_all_keyboards") > 0) {
           (false, None, false) => {
             println!("Error: Must specify a least one --dev-file or --all-keyboards");
@@ -279,6 +404,101 @@ fn main() {
       }
     }
   }
+  else if let Some(m) = m.subcommand_matches("print_cheatsheet") {
+    match load_layout(&m.value_of("default_layout"), &m.value_of("layout_file")) {
+      Err(msg) => {
+        println!("{}", msg);
+        std::process::exit(1);
+      },
+      Ok(layout) => {
+        print!("{}", cheatsheet::to_cheatsheet(&layout, m.value_of("tag")));
+      }
+    }
+  }
+  else if let Some(m) = m.subcommand_matches("check_layout") {
+    match load_layout(&m.value_of("default_layout"), &m.value_of("layout_file")) {
+      Err(msg) => {
+        println!("{}", msg);
+        std::process::exit(1);
+      },
+      Ok(layout) => {
+        for warning in layout_validation::validate_layout(&layout) {
+          println!("Warning: {}", warning);
+        }
+        if m.occurrences_of("conflicts") > 0 {
+          for conflict in layout_validation::conflict_matrix(&layout) {
+            println!("Conflict: {}", conflict);
+          }
+        }
+        if m.occurrences_of("unused_keys") > 0 {
+          let unused = layout_validation::unused_keys(&layout, &key_codes::get_all_keyboard_key_codes());
+          let names: Vec<String> = unused.iter().map(|k| format!("{}", k)).collect();
+          println!("Unused keys: {}", names.join(", "));
+        }
+      }
+    }
+  }
+  else if let Some(m) = m.subcommand_matches("list_reachable_outputs") {
+    let held: Result<Vec<keys::KeyCode>, String> = match m.values_of("held") {
+      None => Ok(vec![]),
+      Some(names) => names.map(|name| {
+        layout_parsing_formatting::parse_key_code(name).map_err(|e| format!("Error in --held: {}", e))
+      }).collect()
+    };
+    match (load_layout(&m.value_of("default_layout"), &m.value_of("layout_file")), held) {
+      (Err(msg), _) | (_, Err(msg)) => {
+        println!("{}", msg);
+        std::process::exit(1);
+      },
+      (Ok(layout), Ok(held)) => {
+        for (k, to) in key_transforms::reachable_outputs(&layout, &held) {
+          let names: Vec<String> = to.iter().map(|k| format!("{}", k)).collect();
+          println!("{} -> [{}]", k, names.join(", "));
+        }
+      }
+    }
+  }
+  else if let Some(m) = m.subcommand_matches("type_text") {
+    match load_layout(&m.value_of("default_layout"), &m.value_of("layout_file")) {
+      Err(msg) => {
+        println!("{}", msg);
+        std::process::exit(1);
+      },
+      Ok(layout) => {
+        let mut mapper = key_transforms::Mapper::for_layout(&layout);
+        for ev in char_production_map::type_string(&mut mapper, m.value_of("TEXT").unwrap()) {
+          println!("{:?}", ev);
+        }
+      }
+    }
+  }
+  else if let Some(m) = m.subcommand_matches("audit_session") {
+    let session_file = m.value_of("session_file").unwrap();
+    let events = match std::fs::read_to_string(session_file) {
+      Err(e) => Err(format!("Error reading {}: {}", session_file, e)),
+      Ok(text) => session_audit::parse_session(&text)
+    };
+    match (load_layout(&m.value_of("default_layout"), &m.value_of("layout_file")), events) {
+      (Err(msg), _) | (_, Err(msg)) => {
+        println!("{}", msg);
+        std::process::exit(1);
+      },
+      (Ok(layout), Ok(events)) => {
+        let report = session_audit::audit_session(&layout, &events);
+        if !report.held_inputs.is_empty() {
+          println!("Still held when the session ends (released before checking): {:?}", report.held_inputs);
+        }
+        if report.stuck_outputs.is_empty() && report.release_all_events.is_empty() {
+          println!("No stuck keys");
+        }
+        else {
+          println!("Stuck keys: {:?}", report.stuck_outputs);
+          println!("Releasing everything would send: {:?}", report.release_all_events);
+          std::process::exit(1);
+        }
+      }
+    }
+  }
   else if let Some(m) = m.subcommand_matches("monitor") {
     match m.value_of("dev_file") {
       None => {
@@ -728,7 +728,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -752,7 +753,8 @@ mod tests {
     let layout = Layout {
       mappings: vec![
         Mapping { from: vec![A], to: vec![B], ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -778,7 +780,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -829,7 +832,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
@@ -876,7 +880,8 @@ mod tests {
       mappings: vec![
         Mapping { from: vec![A], to: vec![A], repeat: Repeat::Disabled, ..Default::default() },
         Mapping { from: vec![B], to: vec![B], repeat: Repeat::Special { keys: vec![LEFTCTRL, C], delay_ms: 130, interval_ms: 30 }, ..Default::default() },
-      ]
+      ],
+      ..Default::default()
     };
     
     let mut ops: VecDeque<TestOp> = VecDeque::new();
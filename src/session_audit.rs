@@ -0,0 +1,170 @@
+// vim: shiftwidth=2
+
+use crate::keys::{Layout, KeyCode, Event, Pressed, Released};
+use crate::key_transforms::Mapper;
+
+// What replaying a session left behind. A session leaks if it leaves
+// `stuck_outputs` or `release_all_events`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AuditReport {
+  // Physical keys still held when the session ends, such as in a
+  // recording cut short. The output they produce is expected to be down.
+  pub held_inputs: Vec<KeyCode>,
+  // Output keys still pressed once the held inputs are released, in
+  // press order
+  pub stuck_outputs: Vec<KeyCode>,
+  // What `release_all` still had to emit after that
+  pub release_all_events: Vec<Event>
+}
+
+// Replays a recorded session through a fresh mapper and reports output
+// keys left held at the end
+pub fn audit_session(layout: &Layout, events: &[Event]) -> AuditReport {
+  let mut mapper = Mapper::for_layout(layout);
+  let mut held_inputs: Vec<KeyCode> = Vec::new();
+  let mut held: Vec<KeyCode> = Vec::new();
+
+  for event in events {
+    track(&mut held_inputs, *event);
+    for out in mapper.step(*event).events {
+      track(&mut held, out);
+    }
+  }
+
+  for k in &held_inputs {
+    for out in mapper.step(Released(*k)).events {
+      track(&mut held, out);
+    }
+  }
+
+  let stuck_outputs = held.clone();
+  let release_all_events = mapper.release_all();
+
+  AuditReport { held_inputs, stuck_outputs, release_all_events }
+}
+
+// Reads a session recorded with `totalmapper monitor`: one event per line,
+// such as `Pressed(CAPSLOCK)`. Blank lines are skipped.
+pub fn parse_session(text: &str) -> Result<Vec<Event>, String> {
+  let mut events = Vec::new();
+  
+  for (i, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    
+    let event = if let Some(name) = line.strip_prefix("Pressed(").and_then(|rest| rest.strip_suffix(')')) {
+      name.parse().map(Pressed)
+    }
+    else if let Some(name) = line.strip_prefix("Released(").and_then(|rest| rest.strip_suffix(')')) {
+      name.parse().map(Released)
+    }
+    else {
+      Err(())
+    };
+    
+    match event {
+      Ok(event) => events.push(event),
+      Err(_) => return Err(format!("Error on line {}: expected an event such as Pressed(CAPSLOCK), found {}", i + 1, line))
+    }
+  }
+  
+  Ok(events)
+}
+
+fn track(held: &mut Vec<KeyCode>, event: Event) {
+  match event {
+    Pressed(k) => {
+      if !held.contains(&k) {
+        held.push(k);
+      }
+    },
+    Released(k) => held.retain(|&k2| k2 != k)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::keys::Mapping;
+  use KeyCode::*;
+
+  #[test]
+  fn test_clean_session() {
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let report = audit_session(&layout, &[
+      Pressed(CAPSLOCK),
+      Pressed(J),
+      Released(J),
+      Released(CAPSLOCK),
+    ]);
+    assert_eq!(report, AuditReport { held_inputs: vec![], stuck_outputs: vec![], release_all_events: vec![] });
+  }
+
+  #[test]
+  fn test_cut_short_session() {
+    // Keys still held at the end are not a leak
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      ..Default::default()
+    };
+    let report = audit_session(&layout, &[
+      Pressed(LEFTSHIFT),
+      Pressed(CAPSLOCK),
+      Pressed(J),
+      Released(J),
+      Pressed(J),
+    ]);
+    assert_eq!(report, AuditReport {
+      held_inputs: vec![LEFTSHIFT, CAPSLOCK, J],
+      stuck_outputs: vec![],
+      release_all_events: vec![]
+    });
+  }
+
+  #[test]
+  fn test_leaky_session() {
+    // Shift is tapped last, so it is left latched: down on the output
+    // with every key up
+    let layout = Layout {
+      mappings: vec![
+        Mapping { from: vec![CAPSLOCK], to: vec![], ..Default::default() },
+        Mapping { from: vec![CAPSLOCK, J], to: vec![LEFT], ..Default::default() },
+      ],
+      latch_modifiers: vec![LEFTSHIFT],
+      ..Default::default()
+    };
+    let report = audit_session(&layout, &[
+      Pressed(CAPSLOCK),
+      Pressed(J),
+      Released(J),
+      Released(CAPSLOCK),
+      Pressed(LEFTSHIFT),
+      Released(LEFTSHIFT),
+    ]);
+    assert_eq!(report, AuditReport {
+      held_inputs: vec![],
+      stuck_outputs: vec![LEFTSHIFT],
+      release_all_events: vec![Released(LEFTSHIFT)]
+    });
+  }
+
+  #[test]
+  fn test_parse_session() {
+    assert_eq!(parse_session("Pressed(CAPSLOCK)\nPressed(J)\n\nReleased(J)\n"), Ok(vec![
+      Pressed(CAPSLOCK), Pressed(J), Released(J),
+    ]));
+    assert!(parse_session("Pressed(CAPSLOCK)\nTapped(J)\n").unwrap_err().contains("line 2"));
+    assert!(parse_session("Pressed(NOTAKEY)\n").is_err());
+  }
+}